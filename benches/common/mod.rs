@@ -0,0 +1,28 @@
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use snli::{cell::Cell, source::Source, vm::Vm};
+
+/// Runs `f` over and over for about a second and prints how long each run
+/// took on average.
+pub fn bench<R>(name: &str, mut f: impl FnMut() -> R) {
+    black_box(f());
+    let start = Instant::now();
+    let mut runs = 0;
+    while start.elapsed() < Duration::from_secs(1) {
+        black_box(f());
+        runs += 1;
+    }
+    println!("{name:40} {:>12.3?} ({runs} runs)", start.elapsed() / runs);
+}
+
+/// Runs a program without printing anything, optionally optimized.
+pub fn run<T: Cell>(source: &Source, optimize: bool) {
+    Vm::<T>::new(source, false)
+        .with_null_output(true)
+        .with_optimize(optimize)
+        .run()
+        .expect("benchmark programs run without errors");
+}
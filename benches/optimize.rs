@@ -0,0 +1,21 @@
+mod common;
+
+use snli::source::Source;
+
+use common::{bench, run};
+
+/// Clears a far cell with `z[0]` 255 times, moving there and back each time.
+const MOVES_AND_CLEARS: &str = "hffz[>>>>>>9z[0]<<<<<<>1<-]";
+
+fn main() {
+    for (name, src) in [
+        ("hello world", include_str!("../programs/hello_world.snl")),
+        ("moves and clears", MOVES_AND_CLEARS),
+    ] {
+        let source = Source::eval(src);
+        bench(&format!("{name}, unoptimized"), || {
+            run::<u8>(&source, false)
+        });
+        bench(&format!("{name}, optimized"), || run::<u8>(&source, true));
+    }
+}
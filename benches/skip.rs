@@ -0,0 +1,21 @@
+mod common;
+
+use snli::source::Source;
+
+use common::{bench, run};
+
+fn main() {
+    for size in [100, 10_000] {
+        let block = "1".repeat(size);
+        // Skipping the block 255 times should cost about the same as skipping
+        // it once, since the jump past it is worked out before running.
+        let once = Source::eval(&format!("1f[{block}]"));
+        let looped = Source::eval(&format!("hffz[>1f[{block}]<-]"));
+        bench(&format!("skip {size} instructions once"), || {
+            run::<u8>(&once, false)
+        });
+        bench(&format!("skip {size} instructions 255 times"), || {
+            run::<u8>(&looped, false)
+        });
+    }
+}
@@ -0,0 +1,19 @@
+mod common;
+
+use snli::source::Source;
+
+use common::{bench, run};
+
+/// Counts down from 100000 in a 32-bit cell.
+const LOOP: &str = "$100000#z[>1<-]";
+
+fn main() {
+    for padding in [0, 100_000] {
+        // The loop should run just as fast at the end of a long file, since
+        // instructions are looked up by index rather than by walking the
+        // source.
+        let source = Source::eval(&("0".repeat(padding) + LOOP));
+        let name = format!("100k iterations after {padding} instructions");
+        bench(&name, || run::<u32>(&source, false));
+    }
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use std::collections::HashMap;
+
+use snli::{source::Source, tape::Tape};
+
+use common::{bench, run};
+
+/// Cells 1 and 2 are multiplied together 255 times, counting down in cell 0.
+const MULTIPLY: &str = "hffz[>3>5<*1<-]";
+
+trait Cells {
+    fn read(&self, index: usize) -> u8;
+    fn write(&mut self, index: usize, value: u8);
+}
+
+impl Cells for Tape<u8> {
+    fn read(&self, index: usize) -> u8 {
+        self.get(index)
+    }
+
+    fn write(&mut self, index: usize, value: u8) {
+        self.set(index, value).unwrap();
+    }
+}
+
+/// The tape as it was stored before it moved to a `Vec`.
+impl Cells for HashMap<usize, u8> {
+    fn read(&self, index: usize) -> u8 {
+        self.get(&index).copied().unwrap_or_default()
+    }
+
+    fn write(&mut self, index: usize, value: u8) {
+        self.insert(index, value);
+    }
+}
+
+/// The cell accesses `MULTIPLY` makes, without the interpreter around them.
+fn multiply(cells: &mut impl Cells) {
+    cells.write(0, 0xff);
+    while cells.read(0) != 0 {
+        cells.write(1, 3);
+        cells.write(2, 5);
+        cells.write(1, cells.read(1).wrapping_mul(cells.read(2)));
+        cells.write(1, 1);
+        cells.write(0, cells.read(0) - cells.read(1));
+    }
+}
+
+fn main() {
+    bench("multiply loop, Tape", || multiply(&mut Tape::new()));
+    bench("multiply loop, HashMap", || multiply(&mut HashMap::new()));
+
+    let source = Source::eval(MULTIPLY);
+    bench("multiply loop, whole program", || run::<u8>(&source, false));
+}
@@ -0,0 +1,106 @@
+use std::{
+    fmt::Display,
+    ops::{Add, Div, Rem, Sub},
+};
+
+pub trait Cell:
+    Copy
+    + Default
+    + Ord
+    + Display
+    + From<u8>
+    + Into<u32>
+    + TryFrom<u32>
+    + TryFrom<usize>
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Div<Output = Self>
+    + Rem<Output = Self>
+{
+    const BITS: u32;
+    const MAX: Self;
+    const HEX_DIGITS: usize = Self::BITS as usize / 4;
+
+    fn truncate(value: u64) -> Self;
+    fn checked_mul(self, rhs: Self) -> Option<Self>;
+    fn checked_pow(self, exp: u32) -> Option<Self>;
+    fn wrapping_neg(self) -> Self;
+    fn count_ones(self) -> u32;
+    fn rotate_left(self, n: u32) -> Self;
+    fn rotate_right(self, n: u32) -> Self;
+    fn signed(self) -> i64;
+    fn from_signed(value: i64) -> Option<Self>;
+
+    fn is_zero(self) -> bool {
+        self == Self::default()
+    }
+
+    fn to_usize(self) -> usize {
+        Into::<u32>::into(self) as usize
+    }
+
+    fn low_byte(self) -> u8 {
+        Into::<u32>::into(self) as u8
+    }
+
+    fn to_char(self) -> char {
+        char::from_u32(self.into()).unwrap_or(char::REPLACEMENT_CHARACTER)
+    }
+
+    fn render(self) -> String {
+        let digits = Self::HEX_DIGITS;
+        match self.to_char() {
+            c if c.is_ascii_graphic() || c == ' ' => format!("{c:digits$}|"),
+            _ => format!("{:0digits$X}|", self.into()),
+        }
+    }
+}
+
+macro_rules! impl_cell {
+    ($t:ty, $signed:ty) => {
+        impl Cell for $t {
+            const BITS: u32 = <$t>::BITS;
+            const MAX: Self = <$t>::MAX;
+
+            fn truncate(value: u64) -> Self {
+                value as $t
+            }
+
+            fn checked_mul(self, rhs: Self) -> Option<Self> {
+                <$t>::checked_mul(self, rhs)
+            }
+
+            fn checked_pow(self, exp: u32) -> Option<Self> {
+                <$t>::checked_pow(self, exp)
+            }
+
+            fn wrapping_neg(self) -> Self {
+                <$t>::wrapping_neg(self)
+            }
+
+            fn count_ones(self) -> u32 {
+                <$t>::count_ones(self)
+            }
+
+            fn rotate_left(self, n: u32) -> Self {
+                <$t>::rotate_left(self, n)
+            }
+
+            fn rotate_right(self, n: u32) -> Self {
+                <$t>::rotate_right(self, n)
+            }
+
+            fn signed(self) -> i64 {
+                self as $signed as i64
+            }
+
+            fn from_signed(value: i64) -> Option<Self> {
+                <$signed>::try_from(value).ok().map(|v| v as $t)
+            }
+        }
+    };
+}
+
+impl_cell!(u8, i8);
+impl_cell!(u16, i16);
+impl_cell!(u32, i32);
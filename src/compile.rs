@@ -0,0 +1,520 @@
+use crate::error::CompileError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    MoveRight,
+    MoveLeft,
+    /// A run of `>` (positive) or `<` (negative) fused by the optimizer.
+    MoveBy(isize),
+    /// A `z[0]` loop fused by the optimizer.
+    SetZero,
+    /// A digit or an `h` literal.
+    Write(u8),
+    /// A `$` literal, checked against the cell size when it runs.
+    Push(u32),
+    /// An `h` or `$` without a valid literal after it.
+    BadLiteral(char),
+    /// Any instruction without operands or jumps of its own.
+    Op(char),
+    /// A `[` without a prefix, which does nothing.
+    Open,
+    /// The `]` closing an `e`/`f`/`E`/`F` block, a switch or a bare `[`.
+    End,
+    If {
+        condition: Condition,
+        end: usize,
+    },
+    Loop {
+        condition: Condition,
+        end: usize,
+    },
+    EndLoop {
+        condition: Condition,
+        start: usize,
+    },
+    Switch {
+        end: usize,
+    },
+    /// `next` is the following case, or the switch's `End` after the last one.
+    Case {
+        label: Label,
+        next: usize,
+    },
+    EndCase {
+        end: usize,
+    },
+    Unknown(char),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    Zero,
+    Nonzero,
+    StackZero,
+    StackNonzero,
+    StackNotEmpty,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Label {
+    Any,
+    Value(u32),
+}
+
+#[derive(Debug, Default)]
+pub struct Program {
+    pub instructions: Vec<Instruction>,
+    /// The source offset each instruction starts at.
+    pub offsets: Vec<usize>,
+    /// How many source instructions each instruction stands for.
+    pub counts: Vec<u64>,
+}
+
+impl Program {
+    pub fn push(&mut self, instruction: Instruction, offset: usize) -> usize {
+        self.instructions.push(instruction);
+        self.offsets.push(offset);
+        self.counts.push(1);
+        self.instructions.len() - 1
+    }
+
+    /// The index of the instruction after `index`, stepping over whole blocks.
+    /// A closing bracket isn't stepped over, since the block it closes has
+    /// already started.
+    pub fn after(&self, index: usize) -> usize {
+        match self.instructions.get(index) {
+            Some(
+                Instruction::If { end, .. }
+                | Instruction::Loop { end, .. }
+                | Instruction::Switch { end },
+            ) => end + 1,
+            Some(Instruction::End | Instruction::EndLoop { .. } | Instruction::EndCase { .. }) => {
+                index
+            }
+            _ => index + 1,
+        }
+    }
+
+    /// The first instruction starting at or after a source offset.
+    pub fn at_offset(&self, offset: usize) -> usize {
+        self.offsets.partition_point(|&o| o < offset)
+    }
+}
+
+const OPS: &str = "ciCsLpPnvNxXobJgl+-u*^ADGBO_{}/=&:rRa!qQ~HVI|@SU#()tdk?";
+
+enum Block {
+    Open,
+    If(usize),
+    Loop(usize, Condition),
+    Switch {
+        start: usize,
+        cases: Vec<usize>,
+        ends: Vec<usize>,
+    },
+    Case,
+}
+
+struct Compiler {
+    chars: Vec<char>,
+    pos: usize,
+    program: Program,
+    blocks: Vec<(Block, usize)>,
+}
+
+pub fn compile(src: &str) -> Result<Program, CompileError> {
+    let mut compiler = Compiler {
+        chars: src.chars().collect(),
+        pos: 0,
+        program: Program::default(),
+        blocks: Vec::new(),
+    };
+    compiler.compile()?;
+    Ok(compiler.program)
+}
+
+impl Compiler {
+    fn current_char(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.current_char();
+        self.pos += 1;
+        c
+    }
+
+    fn open_bracket(&mut self, c: char, start: usize) -> Result<usize, CompileError> {
+        if self.current_char() != Some('[') {
+            return Err(CompileError::MissingBracket {
+                instruction: c,
+                pos: start,
+            });
+        }
+        self.next_char();
+        Ok(self.pos - 1)
+    }
+
+    fn compile(&mut self) -> Result<(), CompileError> {
+        while let Some(c) = self.next_char() {
+            let start = self.pos - 1;
+            let instruction = match c {
+                '0'..='9' => Instruction::Write(c.to_digit(10).unwrap() as u8),
+                'h' => match self.hex_literal() {
+                    Some(value) => Instruction::Write(value),
+                    None => Instruction::BadLiteral('h'),
+                },
+                '$' => match self.decimal_literal() {
+                    Some(value) => Instruction::Push(value),
+                    None => Instruction::BadLiteral('$'),
+                },
+                '>' => Instruction::MoveRight,
+                '<' => Instruction::MoveLeft,
+                'e' | 'f' | 'E' | 'F' => {
+                    let bracket = self.open_bracket(c, start)?;
+                    let condition = match c {
+                        'e' => Condition::Nonzero,
+                        'f' => Condition::Zero,
+                        'E' => Condition::StackNonzero,
+                        _ => Condition::StackZero,
+                    };
+                    let index = self
+                        .program
+                        .push(Instruction::If { condition, end: 0 }, start);
+                    self.blocks.push((Block::If(index), bracket));
+                    continue;
+                }
+                'w' | 'z' | 'W' => {
+                    let bracket = self.open_bracket(c, start)?;
+                    let condition = match c {
+                        'w' => Condition::Zero,
+                        'z' => Condition::Nonzero,
+                        _ => Condition::StackNotEmpty,
+                    };
+                    let index = self
+                        .program
+                        .push(Instruction::Loop { condition, end: 0 }, start);
+                    self.blocks.push((Block::Loop(index, condition), bracket));
+                    continue;
+                }
+                'm' => {
+                    let bracket = self.open_bracket(c, start)?;
+                    let index = self.program.push(Instruction::Switch { end: 0 }, start);
+                    let block = Block::Switch {
+                        start: index,
+                        cases: Vec::new(),
+                        ends: Vec::new(),
+                    };
+                    self.blocks.push((block, bracket));
+                    self.case()?;
+                    continue;
+                }
+                '[' => {
+                    self.program.push(Instruction::Open, start);
+                    self.blocks.push((Block::Open, start));
+                    continue;
+                }
+                ']' => {
+                    self.close(start)?;
+                    continue;
+                }
+                c if OPS.contains(c) => Instruction::Op(c),
+                c if c.is_ascii_whitespace() => continue,
+                c => Instruction::Unknown(c),
+            };
+            self.program.push(instruction, start);
+        }
+
+        match self.blocks.first() {
+            Some(&(_, bracket)) => Err(CompileError::UnbalancedBracket { pos: bracket }),
+            None => Ok(()),
+        }
+    }
+
+    fn close(&mut self, start: usize) -> Result<(), CompileError> {
+        let (block, _) = self
+            .blocks
+            .pop()
+            .ok_or(CompileError::UnbalancedBracket { pos: start })?;
+        match block {
+            Block::Open => {
+                self.program.push(Instruction::End, start);
+            }
+            Block::If(index) => {
+                let end = self.program.push(Instruction::End, start);
+                if let Instruction::If { end: target, .. } = &mut self.program.instructions[index] {
+                    *target = end;
+                }
+            }
+            Block::Loop(index, condition) => {
+                let end = self.program.push(
+                    Instruction::EndLoop {
+                        condition,
+                        start: index,
+                    },
+                    start,
+                );
+                if let Instruction::Loop { end: target, .. } = &mut self.program.instructions[index]
+                {
+                    *target = end;
+                }
+            }
+            Block::Case => {
+                let end = self.program.push(Instruction::EndCase { end: 0 }, start);
+                if let Some((Block::Switch { ends, .. }, _)) = self.blocks.last_mut() {
+                    ends.push(end);
+                }
+                self.case()?;
+            }
+            Block::Switch { .. } => unreachable!("switches are closed by their cases"),
+        }
+        Ok(())
+    }
+
+    /// Compiles the next case label of the innermost switch, or closes the
+    /// switch if there are no cases left.
+    fn case(&mut self) -> Result<(), CompileError> {
+        while self.current_char().is_some_and(|c| c.is_ascii_whitespace()) {
+            self.next_char();
+        }
+        let start = self.pos;
+        let Some(c) = self.next_char() else {
+            return Ok(());
+        };
+        let label = match c {
+            ']' => return self.close_switch(start),
+            '*' => Label::Any,
+            'h' => match self.hex_literal() {
+                Some(value) => Label::Value(value as u32),
+                None => return Err(CompileError::BadLabel { pos: start }),
+            },
+            c => Label::Value(c as u32),
+        };
+        self.open_bracket(c, start)?;
+
+        let index = self
+            .program
+            .push(Instruction::Case { label, next: 0 }, start);
+        if let Some((Block::Switch { cases, .. }, _)) = self.blocks.last_mut() {
+            cases.push(index);
+        }
+        self.blocks.push((Block::Case, self.pos - 1));
+        Ok(())
+    }
+
+    fn close_switch(&mut self, start: usize) -> Result<(), CompileError> {
+        let Some((
+            Block::Switch {
+                start: index,
+                cases,
+                ends,
+            },
+            _,
+        )) = self.blocks.pop()
+        else {
+            unreachable!("cases are only compiled inside a switch");
+        };
+        let end = self.program.push(Instruction::End, start);
+
+        let instructions = &mut self.program.instructions;
+        if let Instruction::Switch { end: target } = &mut instructions[index] {
+            *target = end;
+        }
+        for (i, &case) in cases.iter().enumerate() {
+            if let Instruction::Case { next, .. } = &mut instructions[case] {
+                *next = cases.get(i + 1).copied().unwrap_or(end);
+            }
+        }
+        for case_end in ends {
+            if let Instruction::EndCase { end: target } = &mut instructions[case_end] {
+                *target = end;
+            }
+        }
+        Ok(())
+    }
+
+    fn hex_literal(&mut self) -> Option<u8> {
+        let mut value = 0;
+        let mut digits = 0;
+        while digits < 2
+            && let Some(d) = self.current_char().and_then(|c| c.to_digit(16))
+        {
+            value = value * 16 + d as u8;
+            digits += 1;
+            self.next_char();
+        }
+        (digits == 2).then_some(value)
+    }
+
+    fn decimal_literal(&mut self) -> Option<u32> {
+        let start = self.pos;
+        let mut value: u32 = 0;
+        while let Some(d) = self.current_char().and_then(|c| c.to_digit(10)) {
+            value = value.saturating_mul(10).saturating_add(d);
+            self.next_char();
+        }
+        (self.pos != start).then_some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Instruction::*;
+
+    fn instructions(src: &str) -> Vec<(Instruction, usize)> {
+        let program = compile(src).unwrap();
+        program
+            .instructions
+            .into_iter()
+            .zip(program.offsets)
+            .collect()
+    }
+
+    #[test]
+    fn literals_and_ops() {
+        assert_eq!(
+            instructions("5>h4F<$300n$q"),
+            [
+                (Write(5), 0),
+                (MoveRight, 1),
+                (Write(0x4f), 2),
+                (MoveLeft, 5),
+                (Push(300), 6),
+                (Op('n'), 10),
+                (BadLiteral('$'), 11),
+                (Op('q'), 12),
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_blocks_jump_to_their_ends() {
+        assert_eq!(
+            instructions("e[w[1]z[2]]"),
+            [
+                (
+                    If {
+                        condition: Condition::Nonzero,
+                        end: 7
+                    },
+                    0
+                ),
+                (
+                    Loop {
+                        condition: Condition::Zero,
+                        end: 3
+                    },
+                    2
+                ),
+                (Write(1), 4),
+                (
+                    EndLoop {
+                        condition: Condition::Zero,
+                        start: 1
+                    },
+                    5
+                ),
+                (
+                    Loop {
+                        condition: Condition::Nonzero,
+                        end: 6
+                    },
+                    6
+                ),
+                (Write(2), 8),
+                (
+                    EndLoop {
+                        condition: Condition::Nonzero,
+                        start: 4
+                    },
+                    9
+                ),
+                (End, 10),
+            ]
+        );
+    }
+
+    #[test]
+    fn switch_cases_are_chained() {
+        assert_eq!(
+            instructions("m[ a[1] *[2] ]"),
+            [
+                (Switch { end: 7 }, 0),
+                (
+                    Case {
+                        label: Label::Value(b'a' as u32),
+                        next: 4
+                    },
+                    3
+                ),
+                (Write(1), 5),
+                (EndCase { end: 7 }, 6),
+                (
+                    Case {
+                        label: Label::Any,
+                        next: 7
+                    },
+                    8
+                ),
+                (Write(2), 10),
+                (EndCase { end: 7 }, 11),
+                (End, 13),
+            ]
+        );
+        assert_eq!(instructions("m[]"), [(Switch { end: 1 }, 0), (End, 2)]);
+    }
+
+    #[test]
+    fn after_steps_over_whole_blocks() {
+        let program = compile("?e[1]2m[*[3]]z[4]").unwrap();
+        assert_eq!(program.after(0), 1);
+        assert_eq!(program.after(1), 4);
+        assert_eq!(program.after(2), 3);
+        assert_eq!(program.after(5), 10);
+        assert_eq!(program.after(10), 13);
+        assert_eq!(program.after(13), 14);
+        for closer in [3, 8, 9, 12] {
+            assert_eq!(program.after(closer), closer);
+        }
+    }
+
+    #[test]
+    fn whitespace_is_dropped() {
+        let program = compile("1 \n 2").unwrap();
+        assert_eq!(program.instructions, [Write(1), Write(2)]);
+        assert_eq!(program.offsets, [0, 4]);
+        assert_eq!(program.at_offset(1), 1);
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        let error = |src| compile(src).unwrap_err();
+        assert!(matches!(
+            error("1]"),
+            CompileError::UnbalancedBracket { pos: 1 }
+        ));
+        assert!(matches!(
+            error("1e[2z[3]"),
+            CompileError::UnbalancedBracket { pos: 2 }
+        ));
+        assert!(matches!(
+            error("12z"),
+            CompileError::MissingBracket {
+                instruction: 'z',
+                pos: 2
+            }
+        ));
+        assert!(matches!(
+            error("m[a]"),
+            CompileError::MissingBracket {
+                instruction: 'a',
+                pos: 2
+            }
+        ));
+        assert!(matches!(
+            error("m[h4[]]"),
+            CompileError::BadLabel { pos: 2 }
+        ));
+    }
+}
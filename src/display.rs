@@ -0,0 +1,248 @@
+use std::collections::VecDeque;
+
+use colored::Colorize;
+
+use crate::{
+    cell::Cell,
+    tape::{Cells, Tape},
+};
+
+/// Gaps between written cells longer than this are shown as a count rather
+/// than as blank cells.
+const MAX_GAP: usize = 8;
+
+pub fn display_tape<T: Cell>(tape: &Tape<T>, window: Option<usize>, changed: &[usize]) -> String {
+    let Some(size) = window else {
+        return display_cells(tape, changed);
+    };
+    let width = column_width::<T>();
+    let mut cells: Vec<_> = tape.iter().collect();
+
+    let first = tape.head.saturating_sub(size / 2);
+    let last = first.saturating_add(size.max(1) - 1);
+    let more_left = cells.iter().any(|&(i, _)| i < first);
+    let more_right = cells.iter().any(|&(i, _)| i > last);
+    cells.retain(|&(i, _)| (first..=last).contains(&i));
+    if cells.is_empty() {
+        cells.push((tape.head, T::default()));
+    }
+
+    let mut result = vec![' '; (last - first + 1) * width];
+    for &(i, v) in &cells {
+        let start = (i - first) * width;
+        for (j, c) in v.render().chars().enumerate() {
+            result[start + j] = c;
+        }
+    }
+
+    let mut prefix = String::new();
+    if more_left {
+        prefix += "... ";
+    }
+    prefix += &format!("{first} ");
+
+    let mut row = prefix.clone();
+    for (column, chunk) in result.chunks(width).enumerate() {
+        let index = first + column;
+        row += &highlight(chunk.iter().collect(), index, changed, index == tape.head);
+    }
+
+    let mut result = row;
+    result += &format!(" {last}");
+    if more_right {
+        result += " ...";
+    }
+    result += &wrap_note(tape);
+    result.push('\n');
+    result += &" ".repeat(prefix.len() + (tape.head - first) * width);
+    result += &format!("^ {}\n", tape.head);
+    result += &ruler(first, last, prefix.len(), width);
+    result
+}
+
+/// Renders every written cell, with long runs of unwritten cells between them
+/// collapsed so a far-off cell doesn't cost a column for every cell before it.
+fn display_cells<T: Cell>(tape: &Tape<T>, changed: &[usize]) -> String {
+    let width = column_width::<T>();
+    let mut cells: Vec<_> = tape.iter().map(|(i, v)| (i, Some(v))).collect();
+    if cells.is_empty() {
+        cells.push((tape.head, Some(T::default())));
+    } else if let Err(i) = cells.binary_search_by_key(&tape.head, |&(i, _)| i) {
+        cells.insert(i, (tape.head, None));
+    }
+
+    let mut row = String::new();
+    let mut column = 0;
+    let mut caret = 0;
+    let mut next = 0;
+    for (i, value) in cells {
+        let gap = i - next;
+        let spacing = if gap > MAX_GAP {
+            format!("... {gap} empty ... ")
+        } else {
+            " ".repeat(gap * width)
+        };
+        column += spacing.len();
+        row += &spacing;
+        if i == tape.head {
+            caret = column;
+        }
+        let text = value.map_or(" ".repeat(width), Cell::render);
+        row += &highlight(text, i, changed, false);
+        column += width;
+        next = i + 1;
+    }
+
+    row += &wrap_note(tape);
+    row.push('\n');
+    row += &" ".repeat(caret);
+    row.push('^');
+    row
+}
+
+fn highlight(text: String, index: usize, changed: &[usize], head: bool) -> String {
+    let mut text = text.normal();
+    if changed.contains(&index) {
+        text = text.yellow().bold();
+    }
+    if head {
+        text = text.underline();
+    }
+    text.to_string()
+}
+
+fn wrap_note<T: Cell>(tape: &Tape<T>) -> String {
+    match &tape.data {
+        Cells::Fixed(data) if tape.wrap => format!(" (wraps after cell {})", data.len() - 1),
+        _ => String::new(),
+    }
+}
+
+fn ruler(first: usize, last: usize, indent: usize, width: usize) -> String {
+    let mut ruler = " ".repeat(indent);
+    for i in (first..=last).filter(|i| i % 5 == 0) {
+        let column = indent + (i - first) * width;
+        if ruler.len() < column {
+            ruler += &" ".repeat(column - ruler.len());
+        }
+        if ruler.len() == column {
+            ruler += &i.to_string();
+        }
+    }
+    ruler
+}
+
+fn column_width<T: Cell>() -> usize {
+    T::HEX_DIGITS + 1
+}
+
+pub fn display_stack<T: Cell>(stack: &[T]) -> String {
+    let width = column_width::<T>();
+    let mut result = String::with_capacity(stack.len() * width);
+
+    for &value in stack {
+        result.push_str(&value.render());
+    }
+
+    result
+}
+
+pub fn display_queue<T: Cell>(queue: &VecDeque<T>) -> String {
+    let (front, back) = queue.as_slices();
+    display_stack(front) + &display_stack(back)
+}
+
+pub fn display_hexdump<T: Cell>(start: usize, cells: &[T]) -> String {
+    let digits = T::HEX_DIGITS;
+    let mut lines = Vec::new();
+
+    for (row, chunk) in cells.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk
+            .iter()
+            .map(|&v| format!("{:0digits$X}", v.into()))
+            .collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|v| match v.to_char() {
+                c if c.is_ascii_graphic() || c == ' ' => c,
+                _ => '.',
+            })
+            .collect();
+        lines.push(format!(
+            "{:08X}  {:hex_width$}  |{ascii}|",
+            start + row * 16,
+            hex.join(" "),
+            hex_width = 16 * (digits + 1) - 1
+        ));
+    }
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tape<T: Cell>(head: usize, cells: &[(usize, T)]) -> Tape<T> {
+        colored::control::set_override(false);
+        let mut tape = Tape::new();
+        for &(i, v) in cells {
+            tape.set(i, v).unwrap();
+        }
+        tape.set_head(head).unwrap();
+        tape
+    }
+
+    #[test]
+    fn window_deep_in_the_tape() {
+        let tape = tape::<u8>(1000, &[(0, 1), (999, b'A'), (1000, 2), (1005, 3)]);
+        assert_eq!(
+            tape.display_window(5, &[]),
+            "... 998    A |02|       1002 ...\n              ^ 1000\n              1000"
+        );
+    }
+
+    #[test]
+    fn control_and_high_bytes_render_as_hex() {
+        let tape = tape::<u8>(0, &[(0, 0x00), (1, 0x1f), (2, b'A'), (3, 0x7f), (4, 0xff)]);
+        assert_eq!(tape.display_full(), "00|1F|A |7F|FF|\n^");
+    }
+
+    #[test]
+    fn long_gaps_are_collapsed() {
+        let near = tape::<u8>(14, &[(2, b'A'), (4, 1), (14, 2)]);
+        assert_eq!(
+            near.display_full(),
+            "      A |   01|... 9 empty ... 02|\n                               ^"
+        );
+        let far = tape::<u8>(7, &[(0, 1), (100_000_000_000, 2)]);
+        assert_eq!(
+            far.display_full(),
+            "01|                     ... 99999999992 empty ... 02|\n                     ^"
+        );
+    }
+
+    #[test]
+    fn ruler_lines_up_with_cells() {
+        let tape = tape::<u8>(7, &[(7, 1)]);
+        assert_eq!(
+            tape.display_window(12, &[]),
+            "1                   01|                12\n                    ^ 7\n              5              10"
+        );
+    }
+
+    #[test]
+    fn columns_fit_the_cell_width() {
+        let cells = [(0, b'A'), (1, 0x12), (2, 0)];
+        assert_eq!(
+            tape::<u8>(1, &cells).display_window(5, &[]),
+            "0 A |12|00|       4\n     ^ 1\n  0"
+        );
+
+        let cells = [(0, b'A'.into()), (1, 0x1234), (2, 0)];
+        assert_eq!(
+            tape::<u16>(1, &cells).display_window(5, &[]),
+            "0 A   |1234|0000|           4\n       ^ 1\n  0"
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use std::io;
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum VmError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("bad {kind} input!")]
+    BadInput { kind: &'static str, pos: usize },
+    #[error("bad tape image!")]
+    BadTapeImage,
+    #[error("division by zero!")]
+    DivisionByZero { pos: usize },
+    #[error("tape exceeded {max_cells} cells writing cell {index}!")]
+    TapeFull { max_cells: usize, index: usize },
+    #[error("head moved past the end of the {size}-cell tape!")]
+    TapeOverflow { size: usize },
+    #[error("interrupted!")]
+    Interrupted,
+    #[error("assertion failed: {left} != {right}")]
+    AssertionFailed { pos: usize, left: u32, right: u32 },
+    #[error(transparent)]
+    Compile(#[from] CompileError),
+    #[error("{message}")]
+    Strict { message: String, pos: usize },
+    #[error("exceeded {max_steps} steps!")]
+    StepLimit {
+        max_steps: u64,
+        pos: usize,
+        tape: String,
+    },
+}
+
+impl VmError {
+    pub fn position(&self) -> Option<usize> {
+        match self {
+            VmError::BadInput { pos, .. }
+            | VmError::DivisionByZero { pos }
+            | VmError::AssertionFailed { pos, .. }
+            | VmError::Strict { pos, .. }
+            | VmError::StepLimit { pos, .. } => Some(*pos),
+            VmError::Compile(e) => Some(e.position()),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum CompileError {
+    #[error("unbalanced bracket!")]
+    UnbalancedBracket { pos: usize },
+    #[error("'{instruction}' should have a '[' after!")]
+    MissingBracket { instruction: char, pos: usize },
+    #[error("case label should have two hex digits after 'h'!")]
+    BadLabel { pos: usize },
+}
+
+impl CompileError {
+    pub fn position(&self) -> usize {
+        match self {
+            CompileError::UnbalancedBracket { pos }
+            | CompileError::MissingBracket { pos, .. }
+            | CompileError::BadLabel { pos } => *pos,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{source::Source, vm::Vm};
+
+    fn run_error(src: &str, setup: impl FnOnce(Vm<'_, u8>) -> Vm<'_, u8>) -> VmError {
+        let source = Source::eval(src);
+        setup(Vm::new(&source, false).with_capture(true))
+            .run()
+            .unwrap_err()
+    }
+
+    fn error(src: &str) -> VmError {
+        run_error(src, |vm| vm)
+    }
+
+    #[test]
+    fn compile_errors() {
+        assert!(matches!(
+            error("1]2"),
+            VmError::Compile(CompileError::UnbalancedBracket { pos: 1 })
+        ));
+        assert!(matches!(
+            error("1z[2"),
+            VmError::Compile(CompileError::UnbalancedBracket { pos: 2 })
+        ));
+        assert!(matches!(
+            error("1e2]"),
+            VmError::Compile(CompileError::MissingBracket {
+                instruction: 'e',
+                pos: 1
+            })
+        ));
+        assert!(matches!(
+            error("m[hz[]]"),
+            VmError::Compile(CompileError::BadLabel { pos: 2 })
+        ));
+    }
+
+    #[test]
+    fn runtime_errors() {
+        assert!(matches!(error("8>0</"), VmError::DivisionByZero { pos: 4 }));
+        assert!(matches!(
+            error("1>2<a"),
+            VmError::AssertionFailed {
+                pos: 4,
+                left: 1,
+                right: 2
+            }
+        ));
+        assert!(matches!(
+            run_error("1c", |vm| vm.with_input("abc\n".as_bytes())),
+            VmError::BadInput {
+                kind: "number",
+                pos: 1
+            }
+        ));
+        assert!(matches!(
+            run_error("1>1>1", |vm| vm.with_max_cells(Some(2))),
+            VmError::TapeFull {
+                max_cells: 2,
+                index: 2
+            }
+        ));
+        assert!(matches!(
+            run_error(">>", |vm| vm.with_tape_size(Some(2))),
+            VmError::TapeOverflow { size: 2 }
+        ));
+        assert!(matches!(
+            run_error("1#", |vm| vm.with_strict(true)),
+            VmError::Strict { pos: 1, .. }
+        ));
+        assert!(matches!(
+            run_error("1z[]", |vm| vm.with_max_steps(Some(5))),
+            VmError::StepLimit {
+                max_steps: 5,
+                pos: 3,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn positions() {
+        assert_eq!(error("12]").position(), Some(2));
+        assert_eq!(error("8>0</").position(), Some(4));
+        let overflow = run_error(">>", |vm| vm.with_tape_size(Some(2)));
+        assert_eq!(overflow.position(), None);
+    }
+}
@@ -0,0 +1,9 @@
+pub mod cell;
+pub mod compile;
+pub mod display;
+pub mod error;
+mod optimize;
+pub mod source;
+pub mod tape;
+pub mod validate;
+pub mod vm;
@@ -1,113 +1,244 @@
 use std::{
-    collections::HashMap,
-    fmt::{self, Display, Formatter},
-    fs,
-    path::PathBuf,
+    env, fs, io,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::Ordering,
 };
 
-mod vm;
-
-use clap::Parser;
+use anyhow::bail;
+use clap::{Parser, ValueEnum};
+use crossterm::{cursor, terminal};
 use log::*;
 
-use crate::vm::Vm;
+use snli::{
+    cell::Cell,
+    display::display_stack,
+    error::VmError,
+    source::Source,
+    tape::Tape,
+    validate::{Severity, validate},
+    vm::{INTERRUPTED, Outcome, READING, Vm},
+};
 
 #[derive(Parser)]
 struct Args {
-    file: PathBuf,
+    #[clap(required_unless_present = "eval")]
+    file: Option<PathBuf>,
+
+    #[clap(short, long, conflicts_with = "file")]
+    eval: Option<String>,
 
     #[clap(short, long)]
     debug: bool,
-}
 
-fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
+    #[clap(long)]
+    max_cells: Option<usize>,
+
+    #[clap(long)]
+    tape_size: Option<usize>,
+
+    #[clap(long)]
+    wrap: bool,
+
+    #[clap(long)]
+    null_output: bool,
+
+    #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = "text")]
+    dump_tape: Option<DumpFormat>,
+
+    #[clap(long)]
+    dump_tape_file: Option<PathBuf>,
+
+    #[clap(long)]
+    tape_in: Option<PathBuf>,
+
+    #[clap(long)]
+    dump_stack: bool,
+
+    #[clap(long)]
+    watch: Vec<usize>,
+
+    #[clap(short, long)]
+    quiet: bool,
+
+    #[clap(long, value_parser = parse_level)]
+    log_level: Option<Level>,
+
+    #[clap(long)]
+    keep_whitespace: bool,
+
+    #[clap(long, default_value_t = 0)]
+    delay: u64,
+
+    #[clap(long, default_value_t = 20)]
+    tape_window: usize,
+
+    #[clap(long)]
+    stats: bool,
+
+    #[clap(long)]
+    allow_pause: bool,
+
+    #[clap(long)]
+    no_opt: bool,
 
-    clang_log::init(Level::Trace, "snl");
+    #[clap(long)]
+    strict: bool,
 
-    let src = fs::read_to_string(args.file)?;
+    #[clap(long)]
+    check: bool,
 
-    let mut vm = Vm::new(&src, args.debug);
-    vm.run()?;
+    #[clap(long)]
+    no_clear: bool,
 
-    Ok(())
+    #[clap(long)]
+    max_steps: Option<u64>,
+
+    #[clap(long)]
+    debug_on_error: bool,
+
+    #[clap(long, default_value_t = 8, value_parser = parse_cell_size)]
+    cell_size: u32,
+
+    #[clap(last = true)]
+    program_args: Vec<String>,
 }
 
-#[derive(Default)]
-pub struct Tape<T>
-where
-    T: Copy + Default,
-{
-    data: HashMap<usize, T>,
-    head: usize,
+#[derive(Clone, Copy, ValueEnum)]
+enum DumpFormat {
+    Text,
+    Json,
 }
 
-impl<T: Copy + Default> Tape<T> {
-    pub fn right(&mut self) {
-        self.head += 1;
-    }
+fn parse_level(s: &str) -> Result<Level, String> {
+    s.parse().map_err(|_| format!("unknown log level '{s}'"))
+}
 
-    pub fn left(&mut self) {
-        self.head -= 1;
+fn parse_cell_size(s: &str) -> Result<u32, String> {
+    match s.parse() {
+        Ok(size @ (8 | 16 | 32)) => Ok(size),
+        _ => Err(format!("cell size must be 8, 16 or 32, not '{s}'")),
     }
+}
 
-    pub fn read(&self) -> T {
-        self.data.get(&self.head).copied().unwrap_or_default()
-    }
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
 
-    pub fn write(&mut self, value: T) {
-        self.data.insert(self.head, value);
+    let level = args
+        .log_level
+        .or_else(|| env::var("RUST_LOG").ok()?.parse().ok())
+        .unwrap_or(Level::Warn);
+    clang_log::init(level, "snl");
+    if args.quiet {
+        set_max_level(LevelFilter::Off);
     }
 
-    pub fn new() -> Self {
-        Tape {
-            data: HashMap::new(),
-            head: 0,
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::Relaxed) || READING.load(Ordering::Relaxed) {
+            let _ = crossterm::execute!(io::stdout(), terminal::LeaveAlternateScreen, cursor::Show);
+            process::exit(130);
+        }
+    })?;
+
+    let source = if let Some(code) = &args.eval {
+        Source::eval(code)
+    } else if let Some(file) = &args.file {
+        Source::load(file)?
+    } else {
+        unreachable!("clap requires a file or --eval");
+    };
+
+    for diagnostic in validate(&source.text) {
+        let location = source.locate(diagnostic.pos);
+        match diagnostic.severity {
+            Severity::Warning => warn!("{diagnostic} ({location})"),
+            Severity::Error => bail!("{diagnostic} ({location})"),
         }
     }
-}
+    if args.check {
+        return Ok(());
+    }
 
-impl Display for Tape<u8> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        let mut result = vec![];
-        for i in &self.data {
-            while result.len() <= *i.0 * 3 + 3 {
-                result.push(' ');
+    let result = match args.cell_size {
+        16 => execute::<u16>(&args, &source),
+        32 => execute::<u32>(&args, &source),
+        _ => execute::<u8>(&args, &source),
+    };
+
+    match result {
+        Ok(Outcome::Finished) => Ok(()),
+        Ok(Outcome::Quit(code)) => process::exit(code),
+        Ok(Outcome::Abort(message)) => {
+            if !message.is_empty() {
+                eprintln!("{message}");
+            }
+            process::exit(1)
+        }
+        Err(VmError::Interrupted) => process::exit(130),
+        Err(e) => {
+            if let VmError::StepLimit { tape, .. } = &e {
+                eprintln!("{tape}");
             }
-            if !(*i.1).is_ascii_control() {
-                result[*i.0 * 3] = *i.1 as char;
-                result[*i.0 * 3 + 2] = '|';
-            } else {
-                let formatted = format!("{:X}", *i.1);
-                let mut chars = formatted.chars();
-                result[*i.0 * 3 + 1] = chars.next().unwrap();
-                result[*i.0 * 3] = chars.next().unwrap_or('0');
-                result[*i.0 * 3 + 2] = '|';
+            match e.position() {
+                Some(pos) => Err(anyhow::anyhow!("{e} ({})", source.locate(pos))),
+                None => Err(e.into()),
             }
         }
-
-        f.write_str(result.into_iter().collect::<String>().as_str())?;
-        f.write_str("\n")?;
-        f.write_str(("   ".repeat(self.head) + "^").as_str())
     }
 }
 
-pub fn display_stack(stack: &[u8]) -> String {
-    let mut result = String::with_capacity(stack.len() * 3);
-
-    for i in stack {
-        if !(*i).is_ascii_control() {
-            result.push(*i as char);
-            result.push_str(" |");
-        } else {
-            let formatted = format!("{:X}", *i);
-            if formatted.len() == 1 {
-                result.push('0');
-            }
-            result.push_str(&formatted);
-            result.push('|');
+fn execute<T: Cell>(args: &Args, source: &Source) -> Result<Outcome, VmError> {
+    let mut vm = Vm::<T>::new(source, args.debug)
+        .with_max_cells(args.max_cells)
+        .with_tape_size(args.tape_size)
+        .with_wrap(args.wrap)
+        .with_null_output(args.null_output)
+        .with_watch(args.watch.clone())
+        .with_keep_whitespace(args.keep_whitespace)
+        .with_delay(args.delay)
+        .with_tape_window(args.tape_window)
+        .with_allow_pause(args.allow_pause)
+        .with_optimize(!args.no_opt)
+        .with_args(args.program_args.clone())
+        .with_strict(args.strict)
+        .with_debug_on_error(args.debug_on_error)
+        .with_clear(!args.no_clear)
+        .with_max_steps(args.max_steps);
+    if let Some(path) = &args.tape_in {
+        vm = vm.with_tape(load_tape(path)?)?;
+    }
+    let result = vm.run();
+
+    if let Some(format) = args.dump_tape {
+        let dump = match format {
+            DumpFormat::Text => vm.tape().to_string(),
+            DumpFormat::Json => vm.tape().to_json().to_string(),
+        };
+        match (&args.dump_tape_file, format) {
+            (Some(path), _) => fs::write(path, dump + "\n")?,
+            (None, DumpFormat::Json) => println!("{dump}"),
+            (None, DumpFormat::Text) => eprintln!("{dump}"),
         }
     }
+    if args.dump_stack {
+        eprintln!("{}", display_stack(vm.stack()));
+    }
+    if args.stats {
+        eprintln!("{} steps", vm.steps());
+        eprintln!("{}", vm.tape().stats());
+    }
 
     result
 }
+
+fn load_tape<T: Cell>(path: &Path) -> Result<Tape<T>, VmError> {
+    let bytes = fs::read(path)?;
+    if path.extension().is_some_and(|ext| ext == "json") {
+        serde_json::from_slice(&bytes)
+            .ok()
+            .and_then(|value| Tape::from_json(&value))
+            .ok_or(VmError::BadTapeImage)
+    } else {
+        Ok(Tape::from_bytes(&bytes))
+    }
+}
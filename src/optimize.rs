@@ -0,0 +1,157 @@
+use crate::compile::{Condition, Instruction, Program};
+
+/// Fuses runs of moves and overwritten digits, and `z[0]` loops. Fused
+/// instructions keep the offset of their first source instruction, and an
+/// instruction right after a `?` is never fused so it is still skipped alone.
+/// Moves are only fused when nothing separates them in the source, so the
+/// offset of each one can be worked out from the first.
+pub fn optimize(program: &Program) -> Program {
+    let instructions = &program.instructions;
+    let mut optimized = Program::default();
+    let mut map = Vec::with_capacity(instructions.len());
+
+    let mut fusable = false;
+    let mut i = 0;
+    while i < instructions.len() {
+        let last = optimized.instructions.last_mut();
+        let adjacent = i > 0 && program.offsets[i] == program.offsets[i - 1] + 1;
+        let fused = match (instructions[i], last) {
+            (Instruction::MoveRight, Some(Instruction::MoveBy(n)))
+                if fusable && adjacent && *n > 0 =>
+            {
+                *n += 1;
+                true
+            }
+            (Instruction::MoveLeft, Some(Instruction::MoveBy(n)))
+                if fusable && adjacent && *n < 0 =>
+            {
+                *n -= 1;
+                true
+            }
+            (Instruction::Write(value), Some(Instruction::Write(last))) if fusable => {
+                *last = value;
+                true
+            }
+            _ => false,
+        };
+        if fused {
+            *optimized.counts.last_mut().unwrap() += program.counts[i];
+            map.push(optimized.instructions.len() - 1);
+            i += 1;
+            continue;
+        }
+
+        let clear_loop = [
+            Instruction::Loop {
+                condition: Condition::Nonzero,
+                end: i + 2,
+            },
+            Instruction::Write(0),
+            Instruction::EndLoop {
+                condition: Condition::Nonzero,
+                start: i,
+            },
+        ];
+        if instructions[i..].starts_with(&clear_loop) {
+            let index = optimized.push(Instruction::SetZero, program.offsets[i]);
+            map.extend([index; 3]);
+            fusable = false;
+            i += 3;
+            continue;
+        }
+
+        let instruction = match instructions[i] {
+            Instruction::MoveRight => Instruction::MoveBy(1),
+            Instruction::MoveLeft => Instruction::MoveBy(-1),
+            instruction => instruction,
+        };
+        let index = optimized.push(instruction, program.offsets[i]);
+        optimized.counts[index] = program.counts[i];
+        map.push(index);
+        fusable = i == 0 || instructions[i - 1] != Instruction::Op('?');
+        i += 1;
+    }
+
+    for instruction in &mut optimized.instructions {
+        match instruction {
+            Instruction::If { end, .. }
+            | Instruction::Loop { end, .. }
+            | Instruction::Switch { end }
+            | Instruction::EndCase { end } => *end = map[*end],
+            Instruction::EndLoop { start, .. } => *start = map[*start],
+            Instruction::Case { next, .. } => *next = map[*next],
+            _ => {}
+        }
+    }
+    optimized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::compile;
+    use Instruction::*;
+
+    fn optimized(src: &str) -> Program {
+        optimize(&compile(src).unwrap())
+    }
+
+    #[test]
+    fn runs_are_fused() {
+        let program = optimized(">>><<12>");
+        assert_eq!(
+            program.instructions,
+            [MoveBy(3), MoveBy(-2), Write(2), MoveBy(1)]
+        );
+        assert_eq!(program.offsets, [0, 3, 5, 7]);
+        assert_eq!(program.counts, [3, 2, 2, 1]);
+    }
+
+    #[test]
+    fn clear_loops_are_fused() {
+        let program = optimized("1z[ 0 ]n");
+        assert_eq!(program.instructions, [Write(1), SetZero, Op('n')]);
+        assert_eq!(program.offsets, [0, 1, 7]);
+    }
+
+    #[test]
+    fn nothing_after_a_skip_is_fused() {
+        assert_eq!(
+            optimized("?>>?12?z[0]").instructions,
+            [
+                Op('?'),
+                MoveBy(1),
+                MoveBy(1),
+                Op('?'),
+                Write(1),
+                Write(2),
+                Op('?'),
+                SetZero,
+            ]
+        );
+    }
+
+    #[test]
+    fn jumps_are_remapped() {
+        assert_eq!(
+            optimized("z[>>>]e[z[0]]").instructions,
+            [
+                Loop {
+                    condition: Condition::Nonzero,
+                    end: 2
+                },
+                MoveBy(3),
+                EndLoop {
+                    condition: Condition::Nonzero,
+                    start: 0
+                },
+                If {
+                    condition: Condition::Nonzero,
+                    end: 5
+                },
+                SetZero,
+                End,
+            ]
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use anyhow::{Context as _, bail};
+use std::{
+    collections::HashSet,
+    fmt::{self, Display, Formatter},
+    fs,
+    path::{Path, PathBuf},
+};
+
+pub struct Source {
+    pub text: String,
+    path: PathBuf,
+    len: usize,
+    spans: Vec<Span>,
+}
+
+struct Span {
+    start: usize,
+    path: PathBuf,
+    line: usize,
+}
+
+/// A line and column in a source file, both counting from 1.
+pub struct Location<'a> {
+    pub path: &'a Path,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Display for Location<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.path.display(), self.line, self.column)
+    }
+}
+
+impl Source {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let mut source = Source {
+            text: String::new(),
+            path: path.to_path_buf(),
+            len: 0,
+            spans: Vec::new(),
+        };
+        source.include(path, &mut HashSet::new())?;
+        Ok(source)
+    }
+
+    pub fn eval(code: &str) -> Self {
+        let path = PathBuf::from("-e");
+        let mut source = Source {
+            text: String::new(),
+            path: path.clone(),
+            len: 0,
+            spans: Vec::new(),
+        };
+        for (i, line) in code.split_inclusive('\n').enumerate() {
+            source.push_line(&path, i + 1, line);
+        }
+        source
+    }
+
+    fn include(&mut self, path: &Path, active: &mut HashSet<PathBuf>) -> anyhow::Result<()> {
+        let canonical =
+            fs::canonicalize(path).with_context(|| format!("cannot find {}!", path.display()))?;
+        if !active.insert(canonical.clone()) {
+            bail!("{} includes itself!", path.display());
+        }
+
+        let src = fs::read_to_string(path)?;
+        for (i, line) in src.split_inclusive('\n').enumerate() {
+            if i == 0 && line.starts_with("#!") {
+                // Shebang line, e.g. `#!/usr/bin/env snli`
+            } else if let Some(file) = include_target(line) {
+                let dir = path.parent().unwrap_or(Path::new(""));
+                self.include(&dir.join(file), active)?;
+            } else {
+                self.push_line(path, i + 1, line);
+            }
+        }
+
+        active.remove(&canonical);
+        Ok(())
+    }
+
+    fn push_line(&mut self, path: &Path, line: usize, text: &str) {
+        self.spans.push(Span {
+            start: self.len,
+            path: path.to_path_buf(),
+            line,
+        });
+        self.text += text;
+        self.len += text.chars().count();
+    }
+
+    pub fn locate(&self, pos: usize) -> Location<'_> {
+        if let Some(span) = self.spans.last()
+            && pos >= self.len
+            && self.text.ends_with('\n')
+        {
+            // Past a final newline is the start of the line after it.
+            return Location {
+                path: &span.path,
+                line: span.line + 1,
+                column: pos - self.len + 1,
+            };
+        }
+        let index = self.spans.partition_point(|span| span.start <= pos);
+        match index.checked_sub(1).map(|i| &self.spans[i]) {
+            Some(span) => Location {
+                path: &span.path,
+                line: span.line,
+                column: pos - span.start + 1,
+            },
+            None => Location {
+                path: &self.path,
+                line: 1,
+                column: pos + 1,
+            },
+        }
+    }
+}
+
+fn include_target(line: &str) -> Option<&str> {
+    line.trim()
+        .strip_prefix("include ")?
+        .trim()
+        .strip_prefix('"')?
+        .strip_suffix('"')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn locate(code: &str, pos: usize) -> (usize, usize) {
+        let source = Source::eval(code);
+        let location = source.locate(pos);
+        assert_eq!(location.path, Path::new("-e"));
+        (location.line, location.column)
+    }
+
+    #[test]
+    fn first_line() {
+        assert_eq!(locate("1>2n", 0), (1, 1));
+        assert_eq!(locate("1>2n", 3), (1, 4));
+        assert_eq!(locate("1>2n\n", 3), (1, 4));
+    }
+
+    #[test]
+    fn after_several_newlines() {
+        let code = "1\n\n>2\n  n\n";
+        assert_eq!(locate(code, 1), (1, 2));
+        assert_eq!(locate(code, 2), (2, 1));
+        assert_eq!(locate(code, 4), (3, 2));
+        assert_eq!(locate(code, 8), (4, 3));
+    }
+
+    #[test]
+    fn end_of_file() {
+        assert_eq!(locate("1>2n", 4), (1, 5));
+        assert_eq!(locate("1>2n\n", 5), (2, 1));
+        assert_eq!(locate("1\n\n", 3), (3, 1));
+        assert_eq!(locate("", 0), (1, 1));
+    }
+
+    #[test]
+    fn location_display() {
+        assert_eq!(Source::eval("1\n2").locate(2).to_string(), "-e:2:1");
+    }
+}
@@ -0,0 +1,516 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{self, Display, Formatter},
+};
+
+use crate::{cell::Cell, display::display_tape, error::VmError};
+
+/// How many cells at the start of an unbounded tape are kept in a `Vec`.
+/// Cells past it go in a map, so writing a far-off cell doesn't allocate
+/// every cell before it.
+const DENSE_CELLS: usize = 1 << 16;
+
+#[derive(Clone)]
+pub enum Cells<T> {
+    Growable {
+        cells: Vec<Option<T>>,
+        sparse: BTreeMap<usize, T>,
+        written: usize,
+    },
+    Fixed(Vec<T>),
+}
+
+impl<T> Default for Cells<T> {
+    fn default() -> Self {
+        Cells::Growable {
+            cells: Vec::new(),
+            sparse: BTreeMap::new(),
+            written: 0,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Tape<T>
+where
+    T: Copy + Default,
+{
+    pub(crate) data: Cells<T>,
+    pub(crate) head: usize,
+    pub(crate) max_cells: Option<usize>,
+    pub(crate) wrap: bool,
+    /// The cells written since it was last drained, if anyone is listening.
+    pub(crate) journal: Option<Vec<usize>>,
+}
+
+pub struct TapeStats {
+    pub written: usize,
+    pub range: Option<(usize, usize)>,
+    pub nonzero: usize,
+    pub sum: u64,
+}
+
+impl Display for TapeStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} cells written", self.written)?;
+        if let Some((min, max)) = self.range {
+            write!(f, " (cells {min} to {max})")?;
+        }
+        write!(f, ", {} nonzero summing to {}", self.nonzero, self.sum)
+    }
+}
+
+#[derive(Clone)]
+pub struct TapeSnapshot<T> {
+    data: Cells<T>,
+    head: usize,
+}
+
+impl<T: Copy + Default> Tape<T> {
+    pub fn right(&mut self) -> Result<(), VmError> {
+        let size = self.size();
+        if self.head + 1 >= size {
+            if self.wrap && matches!(self.data, Cells::Fixed(_)) {
+                self.head = 0;
+                return Ok(());
+            }
+            return Err(VmError::TapeOverflow { size });
+        }
+        self.head += 1;
+        Ok(())
+    }
+
+    pub fn left(&mut self) -> bool {
+        if self.head == 0 {
+            if let Cells::Fixed(data) = &self.data
+                && self.wrap
+            {
+                self.head = data.len() - 1;
+                return true;
+            }
+            return false;
+        }
+        self.head -= 1;
+        true
+    }
+
+    pub fn head(&self) -> usize {
+        self.head
+    }
+
+    pub fn set_head(&mut self, head: usize) -> Result<(), VmError> {
+        self.check_range(head, 1)?;
+        self.head = self.wrapped(head);
+        Ok(())
+    }
+
+    pub fn get(&self, index: usize) -> T {
+        self.read_at(self.wrapped(index))
+    }
+
+    pub fn set(&mut self, index: usize, value: T) -> Result<(), VmError> {
+        self.set_range(index, &[value])
+    }
+
+    pub fn snapshot(&self) -> TapeSnapshot<T> {
+        TapeSnapshot {
+            data: self.data.clone(),
+            head: self.head,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &TapeSnapshot<T>) {
+        self.data = snapshot.data.clone();
+        self.head = snapshot.head;
+    }
+
+    pub fn read(&self) -> T {
+        self.read_at(self.head)
+    }
+
+    pub fn try_read(&self) -> Option<T> {
+        self.try_read_at(self.head)
+    }
+
+    pub fn write(&mut self, value: T) -> Result<(), VmError> {
+        self.write_at(self.head, value)
+    }
+
+    pub fn range(&self, start: usize, len: usize) -> Result<Vec<T>, VmError> {
+        self.check_range(start, len)?;
+        let start = self.wrapped(start);
+        Ok((start..start + len)
+            .map(|i| self.read_at(self.wrapped(i)))
+            .collect())
+    }
+
+    pub fn set_range(&mut self, start: usize, values: &[T]) -> Result<(), VmError> {
+        self.check_range(start, values.len())?;
+        let start = self.wrapped(start);
+        for (i, &value) in values.iter().enumerate() {
+            self.write_at(self.wrapped(start + i), value)?;
+        }
+        Ok(())
+    }
+
+    /// The number of cells the head can reach. An unbounded tape stops one
+    /// short of `usize::MAX` so the cell after the head always has an index.
+    fn size(&self) -> usize {
+        match &self.data {
+            Cells::Growable { .. } => usize::MAX,
+            Cells::Fixed(data) => data.len(),
+        }
+    }
+
+    fn check_range(&self, start: usize, len: usize) -> Result<(), VmError> {
+        if self.wrap && matches!(self.data, Cells::Fixed(_)) {
+            return Ok(());
+        }
+        let size = self.size();
+        if start.checked_add(len).is_none_or(|end| end > size) {
+            return Err(VmError::TapeOverflow { size });
+        }
+        Ok(())
+    }
+
+    fn wrapped(&self, index: usize) -> usize {
+        match &self.data {
+            Cells::Fixed(data) if self.wrap => index % data.len(),
+            _ => index,
+        }
+    }
+
+    pub fn read_at(&self, index: usize) -> T {
+        self.try_read_at(index).unwrap_or_default()
+    }
+
+    pub fn try_read_at(&self, index: usize) -> Option<T> {
+        match &self.data {
+            Cells::Growable { cells, .. } if index < DENSE_CELLS => {
+                cells.get(index).copied().flatten()
+            }
+            Cells::Growable { sparse, .. } => sparse.get(&index).copied(),
+            Cells::Fixed(data) => data.get(index).copied(),
+        }
+    }
+
+    fn write_at(&mut self, index: usize, value: T) -> Result<(), VmError> {
+        let is_new = self.try_read_at(index).is_none();
+        match &mut self.data {
+            Cells::Growable {
+                cells,
+                sparse,
+                written,
+            } => {
+                if is_new {
+                    if let Some(max_cells) = self.max_cells
+                        && *written >= max_cells
+                    {
+                        return Err(VmError::TapeFull { max_cells, index });
+                    }
+                    *written += 1;
+                }
+                if index < DENSE_CELLS {
+                    if index >= cells.len() {
+                        cells.resize(index + 1, None);
+                    }
+                    cells[index] = Some(value);
+                } else {
+                    sparse.insert(index, value);
+                }
+            }
+            Cells::Fixed(data) => data[index] = value,
+        }
+        if let Some(journal) = &mut self.journal {
+            journal.push(index);
+        }
+        Ok(())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, T)>
+    where
+        T: Cell,
+    {
+        written(&self.data).into_iter()
+    }
+
+    pub fn stats(&self) -> TapeStats
+    where
+        T: Cell,
+    {
+        let cells = written(&self.data);
+        let nonzero: Vec<u64> = cells
+            .iter()
+            .map(|&(_, v)| Into::<u32>::into(v) as u64)
+            .filter(|&v| v != 0)
+            .collect();
+        TapeStats {
+            written: cells.len(),
+            range: cells.first().zip(cells.last()).map(|(a, b)| (a.0, b.0)),
+            nonzero: nonzero.len(),
+            sum: nonzero.iter().sum(),
+        }
+    }
+
+    pub fn changed_since(&self, snapshot: &TapeSnapshot<T>) -> Vec<usize>
+    where
+        T: Cell,
+    {
+        let before: HashMap<usize, T> = written(&snapshot.data).into_iter().collect();
+        let after: HashMap<usize, T> = written(&self.data).into_iter().collect();
+        let mut changed: Vec<usize> = before
+            .keys()
+            .chain(after.keys())
+            .copied()
+            .filter(|i| before.get(i) != after.get(i))
+            .collect();
+        changed.sort_unstable();
+        changed.dedup();
+        changed
+    }
+
+    pub fn to_json(&self) -> serde_json::Value
+    where
+        T: Cell,
+    {
+        let cells: serde_json::Map<String, serde_json::Value> = self
+            .iter()
+            .map(|(i, v)| (i.to_string(), v.into().into()))
+            .collect();
+        serde_json::json!({ "head": self.head, "cells": cells })
+    }
+
+    pub fn display_full(&self) -> String
+    where
+        T: Cell,
+    {
+        display_tape(self, None, &[])
+    }
+
+    pub fn display_window(&self, size: usize, changed: &[usize]) -> String
+    where
+        T: Cell,
+    {
+        display_tape(self, Some(size), changed)
+    }
+
+    pub fn new() -> Self {
+        Tape {
+            data: Cells::default(),
+            head: 0,
+            max_cells: None,
+            wrap: false,
+            journal: None,
+        }
+    }
+
+    pub fn fixed(size: usize) -> Self {
+        Tape {
+            data: Cells::Fixed(vec![T::default(); size.max(1)]),
+            head: 0,
+            max_cells: None,
+            wrap: false,
+            journal: None,
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self
+    where
+        T: Cell,
+    {
+        let mut tape = Tape::new();
+        let values: Vec<T> = bytes.iter().map(|&b| T::from(b)).collect();
+        tape.set_range(0, &values)
+            .expect("an unbounded tape has room for any file");
+        tape
+    }
+
+    pub fn from_json(value: &serde_json::Value) -> Option<Self>
+    where
+        T: Cell,
+    {
+        let mut tape = Tape::new();
+        tape.set_head(usize::try_from(value.get("head")?.as_u64()?).ok()?)
+            .ok()?;
+        for (index, value) in value.get("cells")?.as_object()? {
+            let value = u32::try_from(value.as_u64()?).ok()?;
+            tape.set(index.parse().ok()?, T::try_from(value).ok()?)
+                .ok()?;
+        }
+        Some(tape)
+    }
+}
+
+fn written<T: Cell>(data: &Cells<T>) -> Vec<(usize, T)> {
+    match data {
+        Cells::Growable { cells, sparse, .. } => cells
+            .iter()
+            .enumerate()
+            .filter_map(|(i, v)| Some((i, (*v)?)))
+            .chain(sparse.iter().map(|(&i, &v)| (i, v)))
+            .collect(),
+        Cells::Fixed(data) => data
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|(_, v)| !v.is_zero())
+            .collect(),
+    }
+}
+
+impl<T: Cell> Display for Tape<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.display_full())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn far_cells_are_stored_sparsely() {
+        let mut tape = Tape::<u8>::new();
+        tape.set(100_000_000_000, 2).unwrap();
+        tape.set(3, 1).unwrap();
+        assert_eq!(tape.get(100_000_000_000), 2);
+        assert_eq!(
+            tape.iter().collect::<Vec<_>>(),
+            [(3, 1), (100_000_000_000, 2)]
+        );
+        if let Cells::Growable { cells, .. } = &tape.data {
+            assert!(cells.len() < DENSE_CELLS);
+        }
+    }
+
+    #[test]
+    fn max_cells_counts_far_cells() {
+        let mut tape = Tape::<u8>::new();
+        tape.max_cells = Some(1);
+        tape.set(1 << 40, 1).unwrap();
+        tape.set(1 << 40, 2).unwrap();
+        assert!(matches!(
+            tape.set(0, 1),
+            Err(VmError::TapeFull {
+                max_cells: 1,
+                index: 0
+            })
+        ));
+    }
+
+    #[test]
+    fn last_cell_cannot_overflow() {
+        let mut tape = Tape::<u8>::new();
+        assert!(tape.set(usize::MAX, 1).is_err());
+        tape.set_head(usize::MAX - 1).unwrap();
+        assert!(matches!(tape.right(), Err(VmError::TapeOverflow { .. })));
+        assert!(tape.range(usize::MAX - 1, 2).is_err());
+    }
+
+    #[test]
+    fn ranges() {
+        let mut tape = Tape::<u8>::new();
+        tape.set_range(2, &[3, 1, 2]).unwrap();
+        assert_eq!(tape.range(1, 5).unwrap(), [0, 3, 1, 2, 0]);
+        assert!(tape.range(4, 0).unwrap().is_empty());
+
+        let mut fixed = Tape::<u8>::fixed(4);
+        assert!(fixed.set_range(2, &[1, 2, 3]).is_err());
+        fixed.wrap = true;
+        fixed.set_range(2, &[1, 2, 3]).unwrap();
+        assert_eq!(fixed.range(0, 4).unwrap(), [3, 0, 1, 2]);
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let mut tape = Tape::<u16>::new();
+        tape.set(0, 300).unwrap();
+        tape.set(2, 0).unwrap();
+        tape.set(100_000_000_000, 7).unwrap();
+        tape.set_head(5).unwrap();
+        let loaded = Tape::<u16>::from_json(&tape.to_json()).unwrap();
+        assert_eq!(
+            loaded.iter().collect::<Vec<_>>(),
+            tape.iter().collect::<Vec<_>>()
+        );
+        assert_eq!(loaded.head(), 5);
+
+        // 300 doesn't fit in a byte.
+        assert!(Tape::<u8>::from_json(&tape.to_json()).is_none());
+        let bad = serde_json::json!({ "head": 0, "cells": { "x": 1 } });
+        assert!(Tape::<u8>::from_json(&bad).is_none());
+    }
+
+    #[test]
+    fn load_bytes() {
+        let tape = Tape::<u16>::from_bytes(b"hi\0");
+        assert_eq!(
+            tape.iter().collect::<Vec<_>>(),
+            [(0, 104), (1, 105), (2, 0)]
+        );
+        assert_eq!(tape.head(), 0);
+    }
+
+    #[test]
+    fn cell_access() {
+        let mut tape = Tape::<u8>::new();
+        assert_eq!(tape.get(9), 0);
+        tape.set(9, 4).unwrap();
+        tape.set(1, 2).unwrap();
+        assert_eq!(tape.get(9), 4);
+        assert_eq!(tape.iter().collect::<Vec<_>>(), [(1, 2), (9, 4)]);
+        tape.set_head(9).unwrap();
+        assert_eq!((tape.head(), tape.read()), (9, 4));
+
+        let mut fixed = Tape::<u8>::fixed(3);
+        assert!(fixed.set_head(3).is_err());
+        assert!(fixed.set(3, 1).is_err());
+        assert_eq!(fixed.head(), 0);
+        fixed.wrap = true;
+        fixed.set_head(4).unwrap();
+        fixed.set(5, 1).unwrap();
+        assert_eq!((fixed.head(), fixed.get(2)), (1, 1));
+    }
+
+    #[test]
+    fn changed_since_a_snapshot() {
+        let mut tape = Tape::<u8>::new();
+        tape.set(0, 1).unwrap();
+        tape.set(1, 2).unwrap();
+        let snapshot = tape.snapshot();
+        assert!(tape.changed_since(&snapshot).is_empty());
+
+        // Rewriting a cell with the same value isn't a change, but writing a
+        // zero to a new cell is, since the cell now shows up.
+        tape.set(0, 1).unwrap();
+        tape.set(1, 3).unwrap();
+        tape.set(5, 0).unwrap();
+        tape.set(100_000_000_000, 4).unwrap();
+        assert_eq!(tape.changed_since(&snapshot), [1, 5, 100_000_000_000]);
+
+        tape.restore(&snapshot);
+        assert!(tape.changed_since(&snapshot).is_empty());
+    }
+
+    #[test]
+    fn stats() {
+        let mut tape = Tape::<u32>::new();
+        assert_eq!(
+            tape.stats().to_string(),
+            "0 cells written, 0 nonzero summing to 0"
+        );
+
+        tape.set(2, u32::MAX).unwrap();
+        tape.set(3, 0).unwrap();
+        tape.set(9, 5).unwrap();
+        let stats = tape.stats();
+        assert_eq!(
+            (stats.written, stats.range, stats.nonzero, stats.sum),
+            (3, Some((2, 9)), 2, u64::from(u32::MAX) + 5)
+        );
+        assert_eq!(
+            stats.to_string(),
+            "3 cells written (cells 2 to 9), 2 nonzero summing to 4294967300"
+        );
+    }
+}
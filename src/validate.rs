@@ -0,0 +1,126 @@
+use std::fmt::{self, Display, Formatter};
+
+use crate::compile::{Instruction, compile};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub pos: usize,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Checks a program without running it. Errors stop it from running, while
+/// warnings are problems it would skip over.
+pub fn validate(src: &str) -> Vec<Diagnostic> {
+    let program = match compile(src) {
+        Ok(program) => program,
+        Err(e) => {
+            return vec![Diagnostic {
+                severity: Severity::Error,
+                pos: e.position(),
+                message: e.to_string(),
+            }];
+        }
+    };
+
+    let mut diagnostics = Vec::new();
+    for (&instruction, &pos) in program.instructions.iter().zip(&program.offsets) {
+        let message = match instruction {
+            Instruction::BadLiteral('h') => "'h' should have two hex digits after!".to_string(),
+            Instruction::BadLiteral(c) => format!("'{c}' should have a number after!"),
+            Instruction::Unknown(c) => format!("Unknown character '{c}'!"),
+            _ => continue,
+        };
+        diagnostics.push(Diagnostic {
+            severity: Severity::Warning,
+            pos,
+            message,
+        });
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagnostics(src: &str) -> Vec<(Severity, usize, String)> {
+        validate(src)
+            .into_iter()
+            .map(|d| (d.severity, d.pos, d.message))
+            .collect()
+    }
+
+    fn error(pos: usize, message: &str) -> Vec<(Severity, usize, String)> {
+        vec![(Severity::Error, pos, message.to_string())]
+    }
+
+    fn warning(pos: usize, message: &str) -> (Severity, usize, String) {
+        (Severity::Warning, pos, message.to_string())
+    }
+
+    #[test]
+    fn valid_programs_have_no_diagnostics() {
+        assert!(diagnostics("h41o $12# 1z[0]\nm[a[n]*[o]]").is_empty());
+    }
+
+    #[test]
+    fn unbalanced_brackets() {
+        assert_eq!(diagnostics("1]"), error(1, "unbalanced bracket!"));
+        assert_eq!(diagnostics("e[1z[0]"), error(1, "unbalanced bracket!"));
+    }
+
+    #[test]
+    fn missing_brackets() {
+        assert_eq!(
+            diagnostics("1 z0"),
+            error(2, "'z' should have a '[' after!")
+        );
+        assert_eq!(
+            diagnostics("m[a1]"),
+            error(2, "'a' should have a '[' after!")
+        );
+    }
+
+    #[test]
+    fn bad_case_labels() {
+        assert_eq!(
+            diagnostics("m[h4[]]"),
+            error(2, "case label should have two hex digits after 'h'!")
+        );
+    }
+
+    #[test]
+    fn bad_literals() {
+        assert_eq!(
+            diagnostics("h4o $o"),
+            [
+                warning(0, "'h' should have two hex digits after!"),
+                warning(4, "'$' should have a number after!"),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_characters() {
+        assert_eq!(
+            diagnostics("1Zn\nyo"),
+            [
+                warning(1, "Unknown character 'Z'!"),
+                warning(4, "Unknown character 'y'!"),
+            ]
+        );
+    }
+}
@@ -1,307 +1,1254 @@
-use super::Tape;
-use crate::display_stack;
-use anyhow::Context as _;
-use colored::Colorize;
+use crate::{
+    cell::Cell,
+    compile::{Condition, Instruction, Label, Program, compile},
+    display::{display_hexdump, display_queue, display_stack},
+    error::VmError,
+    optimize::optimize,
+    source::Source,
+    tape::{Cells, Tape, TapeSnapshot},
+};
+use colored::{ColoredString, Colorize};
 use crossterm::{
     cursor,
     terminal::{self, ClearType},
 };
-use log::error;
-use std::io::{self, Write};
+use log::{error, warn};
+use std::{
+    collections::VecDeque,
+    env,
+    io::{self, BufRead, IsTerminal, Write},
+    mem,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Duration,
+};
+
+const MAX_FILL: usize = 4096;
+const HISTORY_LEN: usize = 256;
+const TAPE_WINDOW: usize = 20;
+
+pub static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Set while the VM is waiting for a line from stdin, where it won't see
+/// [`INTERRUPTED`] until the line ends, so an interrupt should exit straight
+/// away.
+pub static READING: AtomicBool = AtomicBool::new(false);
 
-pub struct Vm<'src> {
+fn read_line(buf: &mut String) -> io::Result<usize> {
+    READING.store(true, Ordering::Relaxed);
+    let result = io::stdin().read_line(buf);
+    READING.store(false, Ordering::Relaxed);
+    result
+}
+
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn new() -> io::Result<Self> {
+        crossterm::execute!(io::stdout(), terminal::EnterAlternateScreen)?;
+        Ok(TerminalGuard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = crossterm::execute!(io::stdout(), terminal::LeaveAlternateScreen, cursor::Show);
+    }
+}
+
+pub struct Vm<'src, T: Cell> {
     ptr: usize,
-    src: &'src str,
-    data: Tape<u8>,
+    source: &'src Source,
+    chars: Vec<char>,
+    program: Program,
+    data: Tape<T>,
     debug: bool,
-    context_stack: Vec<Context>,
-    stack: Vec<u8>,
+    stack: Vec<T>,
+    queue: VecDeque<T>,
+    stdout: Vec<u8>,
+    null_output: bool,
+    watch: Vec<usize>,
+    history: VecDeque<Snapshot<T>>,
+    run_until_head: Option<usize>,
+    steps: u64,
+    keep_whitespace: bool,
+    delay: Duration,
+    tape_window: usize,
+    allow_pause: bool,
+    optimize: bool,
+    args: Vec<String>,
+    strict: bool,
+    debug_on_error: bool,
+    failure: Option<String>,
+    observer: Option<Observer<'src, T>>,
+    input: Option<Box<dyn BufRead + 'src>>,
+    capture: bool,
+    clear: bool,
+    max_steps: Option<u64>,
+}
+
+#[derive(Clone)]
+pub struct Snapshot<T: Cell> {
+    ptr: usize,
+    steps: u64,
+    data: TapeSnapshot<T>,
+    stack: Vec<T>,
+    queue: VecDeque<T>,
+    stdout: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Finished,
+    Quit(i32),
+    Abort(String),
 }
 
-#[derive(Debug)]
-pub enum Context {
-    Zero(usize),
-    While(usize),
+type Observer<'src, T> = Box<dyn FnMut(&VmEvent<T>) + 'src>;
+
+/// Something an instruction did, passed to the observer set with
+/// [`Vm::with_observer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmEvent<T> {
+    CellWritten { index: usize, value: T },
+    HeadMoved { from: usize, to: usize },
+    Output(Vec<u8>),
+    LoopEntered { pos: usize },
 }
 
-impl<'src> Vm<'src> {
-    pub fn new(src: &'src str, debug: bool) -> Self {
+impl<'src, T: Cell> Vm<'src, T> {
+    pub fn new(source: &'src Source, debug: bool) -> Self {
         Vm {
             ptr: 0,
-            src,
+            source,
+            chars: source.text.chars().collect(),
+            program: Program::default(),
             data: Tape::default(),
             debug,
-            context_stack: Vec::new(),
             stack: Vec::new(),
+            queue: VecDeque::new(),
+            stdout: Vec::new(),
+            null_output: false,
+            watch: Vec::new(),
+            history: VecDeque::new(),
+            run_until_head: None,
+            steps: 0,
+            keep_whitespace: false,
+            delay: Duration::ZERO,
+            tape_window: TAPE_WINDOW,
+            allow_pause: false,
+            optimize: false,
+            args: Vec::new(),
+            strict: false,
+            debug_on_error: false,
+            failure: None,
+            observer: None,
+            input: None,
+            capture: false,
+            clear: true,
+            max_steps: None,
         }
     }
 
-    pub fn current_char(&self) -> Option<char> {
-        self.src.chars().nth(self.ptr)
+    pub fn tape(&self) -> &Tape<T> {
+        &self.data
+    }
+
+    pub fn stack(&self) -> &[T] {
+        &self.stack
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
     }
 
-    pub fn next_char(&mut self) -> Option<char> {
-        let c = self.current_char();
+    /// The output captured in debug mode or with [`Vm::with_capture`].
+    pub fn stdout(&self) -> &[u8] {
+        &self.stdout
+    }
+
+    fn capturing(&self) -> bool {
+        self.debug || self.capture
+    }
+
+    fn next_instruction(&mut self) -> Option<Instruction> {
+        let instruction = self.program.instructions.get(self.ptr).copied();
         self.ptr += 1;
-        c
+        instruction
     }
 
-    pub fn seek_char(&mut self, i: usize) {
+    fn seek(&mut self, i: usize) {
         self.ptr = i;
     }
 
-    pub fn debug(&mut self, stdout: &str) -> anyhow::Result<()> {
-        crossterm::execute!(
-            io::stdout(),
-            terminal::Clear(ClearType::Purge),
-            terminal::Clear(ClearType::All),
-            cursor::MoveTo(0, 0)
-        )?;
+    fn bad_input(&self, kind: &'static str) -> VmError {
+        VmError::BadInput {
+            kind,
+            pos: self.offset(),
+        }
+    }
+
+    /// Logs a problem the program can carry on from, followed by what happens
+    /// instead, or fails with it in strict mode.
+    fn warn_at(&self, pos: usize, problem: &str, fallback: &str) -> Result<(), VmError> {
+        self.check_strict(pos, problem)?;
+        let location = self.source.locate(pos);
+        if fallback.is_empty() {
+            warn!("{problem} ({location})");
+        } else {
+            warn!("{problem} {fallback} ({location})");
+        }
+        Ok(())
+    }
+
+    /// Fails with a problem in strict mode. Problems [`validate`] reports
+    /// before the program runs only go through here, so they aren't logged
+    /// again every time they run.
+    ///
+    /// [`validate`]: crate::validate::validate
+    fn check_strict(&self, pos: usize, problem: &str) -> Result<(), VmError> {
+        if self.strict {
+            return Err(VmError::Strict {
+                message: problem.to_string(),
+                pos,
+            });
+        }
+        Ok(())
+    }
+
+    fn warn(&self, problem: &str, fallback: &str) -> Result<(), VmError> {
+        self.warn_at(self.offset(), problem, fallback)
+    }
+
+    /// The source offset of the instruction being run.
+    fn offset(&self) -> usize {
+        let index = self.ptr - 1;
+        self.program
+            .offsets
+            .get(index)
+            .copied()
+            .unwrap_or(self.chars.len())
+    }
+
+    pub fn with_max_cells(mut self, max_cells: Option<usize>) -> Self {
+        self.data.max_cells = max_cells;
+        self
+    }
+
+    pub fn with_tape_size(mut self, tape_size: Option<usize>) -> Self {
+        if let Some(size) = tape_size {
+            self.data = Tape {
+                max_cells: self.data.max_cells,
+                wrap: self.data.wrap,
+                ..Tape::fixed(size)
+            };
+        }
+        self
+    }
+
+    pub fn with_wrap(mut self, wrap: bool) -> Self {
+        self.data.wrap = wrap;
+        self
+    }
+
+    pub fn with_tape(mut self, tape: Tape<T>) -> Result<Self, VmError> {
+        for (index, value) in tape.iter() {
+            self.data.set_range(index, &[value])?;
+        }
+        self.data.set_head(tape.head())?;
+        Ok(self)
+    }
+
+    pub fn with_null_output(mut self, null_output: bool) -> Self {
+        self.null_output = null_output;
+        self
+    }
+
+    pub fn with_watch(mut self, watch: Vec<usize>) -> Self {
+        self.watch = watch;
+        self
+    }
+
+    pub fn with_keep_whitespace(mut self, keep_whitespace: bool) -> Self {
+        self.keep_whitespace = keep_whitespace;
+        self
+    }
+
+    pub fn with_delay(mut self, delay: u64) -> Self {
+        self.delay = Duration::from_millis(delay);
+        self
+    }
+
+    pub fn with_tape_window(mut self, tape_window: usize) -> Self {
+        self.tape_window = tape_window;
+        self
+    }
+
+    pub fn with_allow_pause(mut self, allow_pause: bool) -> Self {
+        self.allow_pause = allow_pause;
+        self
+    }
+
+    pub fn with_optimize(mut self, optimize: bool) -> Self {
+        self.optimize = optimize;
+        self
+    }
+
+    pub fn with_args(mut self, args: Vec<String>) -> Self {
+        self.args = args;
+        self
+    }
+
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn with_debug_on_error(mut self, debug_on_error: bool) -> Self {
+        self.debug_on_error = debug_on_error;
+        self
+    }
+
+    pub fn with_max_steps(mut self, max_steps: Option<u64>) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    pub fn with_clear(mut self, clear: bool) -> Self {
+        self.clear = clear;
+        self
+    }
+
+    pub fn with_capture(mut self, capture: bool) -> Self {
+        self.capture = capture;
+        self
+    }
+
+    pub fn with_observer(mut self, observer: impl FnMut(&VmEvent<T>) + 'src) -> Self {
+        self.observer = Some(Box::new(observer));
+        self
+    }
+
+    /// Reads the program's input from `input` instead of stdin. The debugger
+    /// still reads its commands from stdin.
+    pub fn with_input(mut self, input: impl BufRead + 'src) -> Self {
+        self.input = Some(Box::new(input));
+        self
+    }
+
+    fn prepare(&mut self) -> Result<(), VmError> {
+        if self.data.wrap && matches!(self.data.data, Cells::Growable { .. }) {
+            warn!("--wrap needs a fixed-size tape! Ignoring.");
+        }
+        if self.observer.is_some() {
+            self.data.journal = Some(Vec::new());
+        }
+        self.program = compile(&self.source.text)?;
+        if self.optimize {
+            self.program = optimize(&self.program);
+        }
+        Ok(())
+    }
+
+    fn read_wide(&mut self) -> Result<u16, VmError> {
+        let low = self.data.read();
+        self.data.right()?;
+        let high = self.data.read();
+        self.data.left();
+        Ok(u16::from_le_bytes([low.low_byte(), high.low_byte()]))
+    }
+
+    fn write_wide(&mut self, value: u16) -> Result<(), VmError> {
+        let [low, high] = value.to_le_bytes();
+        self.data.write(T::from(low))?;
+        self.data.right()?;
+        self.data.write(T::from(high))?;
+        self.data.left();
+        Ok(())
+    }
+
+    fn read_input(&mut self) -> io::Result<String> {
+        let mut buf = String::new();
+        match &mut self.input {
+            Some(input) => input.read_line(&mut buf)?,
+            None => read_line(&mut buf)?,
+        };
+        let line = if self.keep_whitespace {
+            let line = buf.strip_suffix('\n').unwrap_or(&buf);
+            line.strip_suffix('\r').unwrap_or(line)
+        } else {
+            buf.trim()
+        };
+        Ok(line.to_string())
+    }
+
+    fn read_string(&mut self) -> Result<Vec<T>, VmError> {
+        let start = self.data.head();
+        let mut string = Vec::new();
+        while !self.data.read().is_zero() {
+            string.push(self.data.read());
+            self.data.right()?;
+        }
+        self.data.set_head(start)?;
+        Ok(string)
+    }
+
+    fn write_string(&mut self, bytes: &[u8]) -> Result<(), VmError> {
+        let start = self.data.head();
+        for &c in bytes {
+            self.data.write(T::from(c))?;
+            self.data.right()?;
+        }
+        self.data.write(T::default())?;
+        self.data.set_head(start)
+    }
+
+    fn output(&mut self, text: &str) -> io::Result<()> {
+        self.output_bytes(text.as_bytes())
+    }
+
+    fn output_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if self.observer.is_some() {
+            self.notify(VmEvent::Output(bytes.to_vec()));
+        }
+        if self.null_output {
+            return Ok(());
+        }
+
+        if self.capturing() {
+            self.stdout.extend_from_slice(bytes);
+        } else {
+            let mut stdout = io::stdout();
+            stdout.write_all(bytes)?;
+            stdout.flush()?;
+        }
+        Ok(())
+    }
+
+    fn notify(&mut self, event: VmEvent<T>) {
+        if let Some(observer) = &mut self.observer {
+            observer(&event);
+        }
+    }
+
+    /// Tells the observer about the cells written and the head moving since
+    /// the instruction started.
+    fn notify_tape(&mut self, head: usize) {
+        let Some(mut written) = self.data.journal.as_mut().map(mem::take) else {
+            return;
+        };
+        written.sort_unstable();
+        written.dedup();
+        for index in written {
+            let value = self.data.read_at(index);
+            self.notify(VmEvent::CellWritten { index, value });
+        }
+        if head != self.data.head() {
+            let to = self.data.head();
+            self.notify(VmEvent::HeadMoved { from: head, to });
+        }
+    }
+
+    fn record(&mut self) {
+        if self.history.len() == HISTORY_LEN {
+            self.history.pop_front();
+        }
+        let mut snapshot = self.snapshot();
+        snapshot.ptr -= 1;
+        self.history.push_back(snapshot);
+    }
+
+    pub fn snapshot(&self) -> Snapshot<T> {
+        Snapshot {
+            ptr: self.ptr,
+            steps: self.steps,
+            data: self.data.snapshot(),
+            stack: self.stack.clone(),
+            queue: self.queue.clone(),
+            stdout: self.stdout.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot<T>) {
+        self.ptr = snapshot.ptr;
+        self.steps = snapshot.steps;
+        self.data.restore(&snapshot.data);
+        self.stack = snapshot.stack.clone();
+        self.queue = snapshot.queue.clone();
+        self.stdout = snapshot.stdout.clone();
+    }
+
+    fn debug_command(&mut self) -> io::Result<bool> {
+        let mut buf = String::new();
+        read_line(&mut buf)?;
+        let mut words = buf.split_whitespace();
+        match (words.next(), words.next()) {
+            (None, _) => Ok(false),
+            (Some("b"), None) => {
+                if let Some(snapshot) = self.history.pop_back() {
+                    self.restore(&snapshot);
+                } else {
+                    self.seek(self.ptr - 1);
+                }
+                Ok(true)
+            }
+            (Some("h"), Some(addr)) => {
+                if let Ok(addr) = addr.parse() {
+                    self.run_until_head = Some(addr);
+                    Ok(false)
+                } else {
+                    error!("Bad address '{addr}'!");
+                    self.seek(self.ptr - 1);
+                    Ok(true)
+                }
+            }
+            (Some("goto"), Some(offset)) => {
+                match offset.parse() {
+                    Ok(offset) if offset < self.chars.len() => {
+                        self.seek(self.program.at_offset(offset));
+                    }
+                    _ => {
+                        error!("Bad source offset '{offset}'!");
+                        self.seek(self.ptr - 1);
+                    }
+                }
+                Ok(true)
+            }
+            (Some("set"), Some(addr)) => {
+                let value = words.next().and_then(parse_number);
+                match (addr.parse(), value.and_then(|v| T::try_from(v).ok())) {
+                    (Err(_), _) => error!("Bad address '{addr}'!"),
+                    (_, None) => error!("Bad value! Expected a number up to {}.", T::MAX),
+                    (Ok(addr), Some(value)) => {
+                        if let Err(e) = self.data.set(addr, value) {
+                            error!("Cannot set cell {addr}: {e}");
+                        }
+                    }
+                }
+                self.seek(self.ptr - 1);
+                Ok(true)
+            }
+            _ => {
+                error!("Unknown command '{}'!", buf.trim());
+                self.seek(self.ptr - 1);
+                Ok(true)
+            }
+        }
+    }
+
+    /// The start and end of the source line holding an offset, not counting
+    /// its newline.
+    fn line_around(&self, offset: usize) -> (usize, usize) {
+        let start = self.chars[..offset]
+            .iter()
+            .rposition(|&c| c == '\n')
+            .map_or(0, |i| i + 1);
+        let end = self.chars[start..]
+            .iter()
+            .position(|&c| c == '\n')
+            .map_or(self.chars.len(), |i| start + i);
+        (start, end)
+    }
+
+    fn debug(&mut self) -> io::Result<()> {
+        if self.clear {
+            crossterm::execute!(
+                io::stdout(),
+                terminal::Clear(ClearType::Purge),
+                terminal::Clear(ClearType::All),
+                cursor::MoveTo(0, 0),
+                cursor::Hide
+            )?;
+        } else {
+            println!("{}", "-".repeat(40));
+        }
 
-        print!("{stdout}");
-        if !stdout.ends_with("\n") {
+        io::stdout().write_all(&self.stdout)?;
+        if !self.stdout.ends_with(b"\n") {
             println!("{}\n", "%".black().on_white());
         } else {
             println!();
         }
 
-        println!("{}", self.src);
-        println!("{}^", " ".repeat(self.ptr - 1));
+        let offset = self.offset();
+        let (start, end) = self.line_around(offset);
+        for (i, &c) in self.chars[start..end].iter().enumerate() {
+            if start + i == offset {
+                print!("{}", highlight(c).bold().reversed());
+            } else {
+                print!("{}", highlight(c));
+            }
+        }
+        println!();
+        let location = self.source.locate(offset);
+        println!(
+            "{}^ line {}, col {}",
+            " ".repeat(offset - start),
+            location.line,
+            location.column
+        );
 
         println!();
 
-        println!("{}", self.data);
+        let changed = match self.history.back() {
+            Some(snapshot) => self.data.changed_since(&snapshot.data),
+            None => Vec::new(),
+        };
+        println!("{}", self.data.display_window(self.tape_window, &changed));
+        println!("step {}", self.steps());
+        if let Some(failure) = &self.failure {
+            println!("{}", failure.red());
+        }
+        if !self.watch.is_empty() {
+            let watches: Vec<String> = self
+                .watch
+                .iter()
+                .map(|&addr| match self.data.try_read_at(addr) {
+                    Some(value) => format!("[{addr}] = {value}"),
+                    None => format!("[{addr}] = -"),
+                })
+                .collect();
+            println!("{}", watches.join("  "));
+        }
 
         println!("{}", display_stack(&self.stack));
+        println!("{}", display_queue(&self.queue));
         println!();
 
         Ok(())
     }
 
-    pub fn run(&mut self) -> anyhow::Result<()> {
-        let mut stdout = String::new();
+    fn holds(&self, condition: Condition) -> bool {
+        match condition {
+            Condition::Zero => self.data.read().is_zero(),
+            Condition::Nonzero => !self.data.read().is_zero(),
+            Condition::StackZero => self.stack.last().copied().unwrap_or_default().is_zero(),
+            Condition::StackNonzero => !self.stack.last().copied().unwrap_or_default().is_zero(),
+            Condition::StackNotEmpty => !self.stack.is_empty(),
+        }
+    }
 
-        while let Some(c) = self.next_char() {
-            if self.debug {
-                self.debug(&stdout)?;
-            }
-
-            match c {
-                '0'..='9' => {
-                    self.data.write(c.to_digit(10).unwrap() as u8);
-                }
-                '>' => self.data.right(),
-                '<' => self.data.left(),
-                'c' => {
-                    let mut buf = String::new();
-                    io::stdin().read_line(&mut buf)?;
-                    self.data
-                        .write(buf.trim().parse::<u8>().context("bad number input!")?);
-                }
-                'i' => {
-                    let mut buf = String::new();
-                    io::stdin().read_line(&mut buf)?;
-                    self.data
-                        .write(buf.trim().parse::<char>().context("bad character input!")? as u8);
-                }
-                's' => {
-                    let mut buf = String::new();
-                    io::stdin().read_line(&mut buf)?;
-                    let trimmed = buf.trim();
-                    for c in trimmed.bytes() {
-                        self.data.write(c);
-                        self.data.right();
-                    }
-                    self.data.write(0);
-                    self.data.head -= trimmed.len();
-                }
-                'p' => {
-                    let mut i = 0;
-                    while self.data.read() != 0 {
-                        let print = format!("{}", self.data.read() as char);
-                        if self.debug {
-                            stdout += print.as_str();
-                        } else {
-                            print!("{print}");
-                        }
+    /// Runs one instruction, returning an outcome if it stops the program.
+    fn step(&mut self, instruction: Instruction) -> Result<Option<Outcome>, VmError> {
+        use Instruction::*;
 
-                        i += 1;
-                        self.data.right();
-                    }
-                    self.data.head -= i;
-                    io::stdout().flush()?;
+        match instruction {
+            Write(value) => self.data.write(T::from(value))?,
+            Push(value) => match T::try_from(value) {
+                Ok(value) => self.stack.push(value),
+                Err(_) => self.warn(
+                    &format!("'$' should have a number up to {} after!", T::MAX),
+                    "Ignoring.",
+                )?,
+            },
+            BadLiteral('h') => {
+                self.check_strict(self.offset(), "'h' should have two hex digits after!")?;
+            }
+            BadLiteral(c) => {
+                self.check_strict(self.offset(), &format!("'{c}' should have a number after!"))?;
+            }
+            MoveRight => self.data.right()?,
+            MoveLeft => {
+                if !self.data.left() {
+                    self.warn("Cannot move left of cell 0!", "Ignoring.")?;
                 }
-                'n' => {
-                    let print = format!("{}", self.data.read());
-                    if self.debug {
-                        stdout += print.as_str();
+            }
+            MoveBy(n) => {
+                let moves = n.unsigned_abs();
+                for i in 0..moves {
+                    let result = if n > 0 {
+                        self.data.right()
+                    } else if !self.data.left() {
+                        let offset = self.offset() + i;
+                        self.warn_at(offset, "Cannot move left of cell 0!", "Ignoring.")
                     } else {
-                        print!("{print}");
+                        Ok(())
+                    };
+                    if result.is_err() {
+                        // Only count the moves made, as if they weren't fused.
+                        self.steps -= (moves - i - 1) as u64;
                     }
-                    io::stdout().flush()?;
+                    result?;
                 }
-                'o' => {
-                    let print = format!("{}", self.data.read() as char);
-                    if self.debug {
-                        stdout += print.as_str();
-                    } else {
-                        print!("{print}");
-                    }
-                    io::stdout().flush()?;
-                }
-                '+' => {
-                    let left = self.data.read();
-                    self.data.right();
-                    let right = self.data.read();
-                    self.data.left();
-                    self.data.write(left + right);
-                }
-                '-' => {
-                    let left = self.data.read();
-                    self.data.right();
-                    let right = self.data.read();
-                    self.data.left();
-                    self.data.write(left - right);
-                }
-                '*' => {
-                    let left = self.data.read();
-                    self.data.right();
-                    let right = self.data.read();
-                    self.data.left();
-                    if let Some(v) = left.checked_mul(right) {
-                        self.data.write(v);
-                    } else {
-                        error!("Cannot multiply {left} * {right}!");
-                    }
+            }
+            SetZero => {
+                if !self.data.read().is_zero() {
+                    self.notify(VmEvent::LoopEntered { pos: self.offset() });
+                    self.data.write(T::default())?;
+                    self.steps += 2;
                 }
-                '/' => {
-                    let left = self.data.read();
-                    self.data.right();
-                    let right = self.data.read();
-                    self.data.left();
-                    self.data.write(left / right);
-                }
-                '[' => {}
-                ']' => match self.context_stack.pop() {
-                    None => {}
-                    Some(c) => match c {
-                        Context::Zero(ptr) => {
-                            if self.data.read() != 0 {
-                                self.seek_char(ptr);
-                                self.context_stack.push(c);
-                            }
+            }
+            Op('|') => self.data.set_head(0)?,
+            Op('c') => {
+                let line = self.read_input()?;
+                let value = parse_number(&line).and_then(|n| T::try_from(n).ok());
+                self.data.write(value.ok_or(self.bad_input("number"))?)?;
+            }
+            Op('C') => {
+                let line = self.read_input()?;
+                let value = parse_number(&line).and_then(|n| u16::try_from(n).ok());
+                self.write_wide(value.ok_or(self.bad_input("number"))?)?;
+            }
+            Op('i') => {
+                let line = self.read_input()?;
+                let c = line
+                    .parse::<char>()
+                    .map_err(|_| self.bad_input("character"))?;
+                self.data.write(T::truncate(c as u64))?;
+            }
+            Op('s') => {
+                let line = self.read_input()?;
+                self.write_string(line.as_bytes())?;
+            }
+            Op('V') => {
+                let name: String = self.read_string()?.into_iter().map(Cell::to_char).collect();
+                let value = env::var(name).unwrap_or_default();
+                self.write_string(value.as_bytes())?;
+            }
+            Op('I') => {
+                let index = self.data.read().to_usize();
+                let arg = self.args.get(index).cloned().unwrap_or_default();
+                self.write_string(arg.as_bytes())?;
+            }
+            Op('L') => {
+                let line = self.read_input()?;
+                let start = self.data.head();
+                for word in line.split_whitespace() {
+                    match parse_number(word).and_then(|n| T::try_from(n).ok()) {
+                        Some(value) => {
+                            self.data.write(value)?;
+                            self.data.right()?;
                         }
-                        Context::While(ptr) => {
-                            if self.data.read() == 0 {
-                                self.seek_char(ptr);
-                                self.context_stack.push(c);
-                            }
+                        None => {
+                            let problem = format!("Cannot read '{word}' as a number!");
+                            self.warn(&problem, "Skipping.")?;
                         }
-                    },
-                },
-                '@' => {
-                    self.stack.push(self.data.read());
-                }
-                '#' => {
-                    if let Some(v) = self.stack.pop() {
-                        self.data.write(v);
                     }
                 }
-                'e' => {
-                    if self.current_char() != Some('[') {
-                        error!("'e' should have a ']' after! Ignoring.");
-                    } else {
-                        self.next_char();
-                    }
-
-                    if self.data.read() == 0 {
-                        let mut stack_size = 0;
-                        while let Some(c) = self.next_char() {
-                            if c == ']' && stack_size == 0 {
-                                break;
-                            } else if c == ']' {
-                                stack_size -= 1;
-                            } else if c == '[' {
-                                stack_size += 1;
-                            }
-                        }
-                    }
+                self.data.write(T::default())?;
+                self.data.set_head(start)?;
+            }
+            Op('p') => {
+                let start = self.data.head();
+                let mut print = String::new();
+                while !self.data.read().is_zero() {
+                    print.push(self.data.read().to_char());
+                    self.data.right()?;
                 }
-                'f' => {
-                    if self.current_char() != Some('[') {
-                        error!("'f' should have a ']' after! Ignoring.");
-                    } else {
-                        self.next_char();
+                self.data.set_head(start)?;
+                self.output(&print)?;
+            }
+            Op('P') => {
+                self.data.right()?;
+                let count = self.data.read().to_usize();
+                self.data.left();
+                let mut print = String::new();
+                let start = self.data.head();
+                for i in 0..count {
+                    if i > 0 {
+                        self.data.right()?;
                     }
-
-                    if self.data.read() != 0 {
-                        let mut stack_size = 0;
-                        while let Some(c) = self.next_char() {
-                            if c == ']' && stack_size == 0 {
-                                break;
-                            } else if c == ']' {
-                                stack_size -= 1;
-                            } else if c == '[' {
-                                stack_size += 1;
-                            }
-                        }
+                    print.push(self.data.read().to_char());
+                }
+                self.data.set_head(start)?;
+                self.output(&print)?;
+            }
+            Op('n') => {
+                let print = format!("{}", self.data.read());
+                self.output(&print)?;
+            }
+            Op('v') => {
+                let print = format!("{}", self.data.read().signed());
+                self.output(&print)?;
+            }
+            Op('N') => {
+                let print = format!("{}", self.read_wide()?);
+                self.output(&print)?;
+            }
+            Op('x') => {
+                let value: u32 = self.data.read().into();
+                let print = format!("{value:0digits$X}", digits = T::HEX_DIGITS);
+                self.output(&print)?;
+            }
+            Op('o') => {
+                let print = format!("{}", self.data.read().to_char());
+                self.output(&print)?;
+            }
+            Op('b') => {
+                let byte = self.data.read().low_byte();
+                self.output_bytes(&[byte])?;
+            }
+            Op('J') => {
+                if self.capturing() {
+                    self.stdout.clear();
+                } else if io::stdout().is_terminal() {
+                    crossterm::execute!(
+                        io::stdout(),
+                        terminal::Clear(ClearType::All),
+                        cursor::MoveTo(0, 0)
+                    )?;
+                }
+            }
+            Op('g') => {
+                let row: u32 = self.data.read().into();
+                self.data.right()?;
+                let column: u32 = self.data.read().into();
+                self.data.left();
+                if !self.capturing() && io::stdout().is_terminal() {
+                    crossterm::execute!(io::stdout(), cursor::MoveTo(column as u16, row as u16))?;
+                }
+            }
+            Op('l') => {
+                let value = self.data.read();
+                self.data.right()?;
+                let mut count = self.data.read().to_usize();
+                self.data.left();
+                if count > MAX_FILL {
+                    let fallback = format!("Filling {MAX_FILL} instead.");
+                    self.warn(&format!("Cannot fill {count} cells!"), &fallback)?;
+                    count = MAX_FILL;
+                }
+                let start = self.data.head();
+                for i in 0..count {
+                    if i > 0 {
+                        self.data.right()?;
                     }
+                    self.data.write(value)?;
                 }
-                'w' => {
-                    if self.current_char() != Some('[') {
-                        error!("'w' should have a ']' after! Ignoring.");
-                    } else {
-                        self.next_char();
+                self.data.set_head(start)?;
+            }
+            Op('+') => {
+                let left = self.data.read();
+                self.data.right()?;
+                let right = self.data.read();
+                self.data.left();
+                self.data.write(left + right)?;
+            }
+            Op('-') => {
+                let left = self.data.read();
+                self.data.right()?;
+                let right = self.data.read();
+                self.data.left();
+                self.data.write(left - right)?;
+            }
+            Op('u') => {
+                let left = self.data.read().signed();
+                self.data.right()?;
+                let right = self.data.read().signed();
+                self.data.left();
+                if let Some(v) = T::from_signed(left - right) {
+                    self.data.write(v)?;
+                } else {
+                    self.warn(&format!("Cannot subtract {left} - {right}!"), "")?;
+                }
+            }
+            Op('*') => {
+                let left = self.data.read();
+                self.data.right()?;
+                let right = self.data.read();
+                self.data.left();
+                if let Some(v) = left.checked_mul(right) {
+                    self.data.write(v)?;
+                } else {
+                    self.warn(&format!("Cannot multiply {left} * {right}!"), "")?;
+                }
+            }
+            Op('^') => {
+                let left = self.data.read();
+                self.data.right()?;
+                let right = self.data.read();
+                self.data.left();
+                if let Some(v) = left.checked_pow(right.into()) {
+                    self.data.write(v)?;
+                } else {
+                    self.warn(&format!("Cannot raise {left} ^ {right}!"), "")?;
+                }
+            }
+            Op('A') => {
+                let left = self.read_wide()?;
+                self.data.right()?;
+                self.data.right()?;
+                let right = self.read_wide()?;
+                self.data.left();
+                self.data.left();
+                if let Some(v) = left.checked_add(right) {
+                    self.write_wide(v)?;
+                } else {
+                    self.warn(&format!("Cannot add {left} + {right}!"), "")?;
+                }
+            }
+            Op('D') => {
+                let left = self.read_wide()?;
+                self.data.right()?;
+                self.data.right()?;
+                let right = self.read_wide()?;
+                self.data.left();
+                self.data.left();
+                if let Some(v) = left.checked_sub(right) {
+                    self.write_wide(v)?;
+                } else {
+                    self.warn(&format!("Cannot subtract {left} - {right}!"), "")?;
+                }
+            }
+            Op('G') => {
+                let mut left = self.data.read();
+                self.data.right()?;
+                let mut right = self.data.read();
+                self.data.left();
+                while !right.is_zero() {
+                    (left, right) = (right, left % right);
+                }
+                self.data.write(left)?;
+            }
+            Op('B') => {
+                let value = self.data.read();
+                self.data.write(T::from(value.count_ones() as u8))?;
+            }
+            Op('O') => {
+                let value = self.data.read();
+                self.data.write(value % T::from(2))?;
+            }
+            Op('_') => {
+                let value = self.data.read();
+                self.data.write(value.wrapping_neg())?;
+            }
+            Op('{') => {
+                let value = self.data.read();
+                self.data.right()?;
+                let amount: u32 = self.data.read().into();
+                self.data.left();
+                self.data.write(value.rotate_left(amount % T::BITS))?;
+            }
+            Op('}') => {
+                let value = self.data.read();
+                self.data.right()?;
+                let amount: u32 = self.data.read().into();
+                self.data.left();
+                self.data.write(value.rotate_right(amount % T::BITS))?;
+            }
+            Op('/') => {
+                let left = self.data.read();
+                self.data.right()?;
+                let right = self.data.read();
+                self.data.left();
+                if right.is_zero() {
+                    return Err(VmError::DivisionByZero { pos: self.offset() });
+                }
+                self.data.write(left / right)?;
+            }
+            Open | End => {}
+            If { condition, end } => {
+                if !self.holds(condition) {
+                    self.seek(end + 1);
+                }
+            }
+            Loop { condition, end } => {
+                if self.holds(condition) {
+                    self.notify(VmEvent::LoopEntered { pos: self.offset() });
+                } else {
+                    self.seek(end + 1);
+                }
+            }
+            EndLoop { condition, start } => {
+                if self.holds(condition) {
+                    self.seek(start + 1);
+                }
+            }
+            Switch { end } => {
+                let value: u32 = self.data.read().into();
+                let mut case = self.ptr;
+                while let Some(&Case { label, next }) = self.program.instructions.get(case) {
+                    if label == Label::Any || label == Label::Value(value) {
+                        break;
                     }
+                    case = next;
+                }
+                self.seek(if case == end { end + 1 } else { case + 1 });
+            }
+            Case { .. } => {}
+            EndCase { end } => self.seek(end + 1),
+            Op('=') => {
+                let start = self.data.head();
+                let first = self.read_string()?;
+                for _ in 0..=first.len() {
+                    self.data.right()?;
+                }
+                let second = self.read_string()?;
+                self.data.set_head(start)?;
+                self.data.write(T::from((first == second) as u8))?;
+            }
+            Op('&') => {
+                let start = self.data.head();
+                let first = self.read_string()?;
+                for _ in 0..=first.len() {
+                    self.data.right()?;
+                }
+                let second = self.read_string()?;
+                self.data.left();
+                for &c in &second {
+                    self.data.write(c)?;
+                    self.data.right()?;
+                }
+                self.data.write(T::default())?;
+                self.data.set_head(start)?;
+            }
+            Op(':') => {
+                let start = self.data.head();
+                let haystack = self.read_string()?;
+                for _ in 0..=haystack.len() {
+                    self.data.right()?;
+                }
+                let needle = self.read_string()?;
+                self.data.set_head(start)?;
+                let found = if needle.is_empty() {
+                    Some(0)
+                } else {
+                    haystack.windows(needle.len()).position(|w| w == needle)
+                };
+                let offset = found.and_then(|i| T::try_from(i).ok());
+                self.data.write(offset.unwrap_or(T::MAX))?;
+            }
+            Op('r') => {
+                self.data.right()?;
+                let count = self.data.read().to_usize();
+                self.data.left();
+                let mut values = self.data.range(self.data.head(), count)?;
+                values.sort_unstable();
+                self.data.set_range(self.data.head(), &values)?;
+            }
+            Op('X') => {
+                self.data.right()?;
+                let count = self.data.read().to_usize();
+                self.data.left();
+                let values = self.data.range(self.data.head(), count)?;
+                eprintln!("{}", display_hexdump(self.data.head(), &values));
+            }
+            Op('R') => {
+                let index = self.data.read().to_usize();
+                let byte = self.source.text.as_bytes().get(index).copied().unwrap_or(0);
+                self.data.right()?;
+                self.data.write(T::from(byte))?;
+                self.data.left();
+            }
+            Op('a') => {
+                let left = self.data.read();
+                self.data.right()?;
+                let right = self.data.read();
+                self.data.left();
+                if left != right {
+                    return Err(VmError::AssertionFailed {
+                        pos: self.offset(),
+                        left: left.into(),
+                        right: right.into(),
+                    });
+                }
+            }
+            Op('!') => {
+                if !self.debug {
+                    eprintln!("{}", self.data);
+                    eprintln!("head {}", self.data.head());
+                    eprintln!("{}", display_stack(&self.stack));
+                }
+            }
+            Op('q') => {
+                let code = Into::<u32>::into(self.data.read()) as i32;
+                return Ok(Some(Outcome::Quit(code)));
+            }
+            Op('Q') => {
+                let message = self.read_string()?.into_iter().map(Cell::to_char).collect();
+                return Ok(Some(Outcome::Abort(message)));
+            }
+            Op('~') => {
+                if self.allow_pause && !self.debug {
+                    eprintln!("{}", self.data);
+                    let location = self.source.locate(self.offset());
+                    eprint!("paused at {location}, press Enter to continue");
+                    read_line(&mut String::new())?;
+                }
+            }
+            Op('H') => {
+                eprintln!("head {}", self.data.head());
+            }
+            Op('@') => {
+                self.stack.push(self.data.read());
+            }
+            Op('S') => {
+                let string = self.read_string()?;
+                self.stack.push(T::default());
+                self.stack.extend(string.iter().rev());
+            }
+            Op('U') => {
+                let start = self.data.head();
+                while let Some(c) = self.stack.pop()
+                    && !c.is_zero()
+                {
+                    self.data.write(c)?;
+                    self.data.right()?;
+                }
+                self.data.write(T::default())?;
+                self.data.set_head(start)?;
+            }
+            Op('#') => {
+                if let Some(v) = self.stack.pop() {
+                    self.data.write(v)?;
+                } else {
+                    self.warn("Cannot pop from an empty stack!", "Ignoring.")?;
+                }
+            }
+            Op('(') => {
+                self.queue.push_back(self.data.read());
+            }
+            Op(')') => {
+                if let Some(v) = self.queue.pop_front() {
+                    self.data.write(v)?;
+                } else {
+                    self.warn("Cannot dequeue from an empty queue!", "Ignoring.")?;
+                }
+            }
+            Op('t') => {
+                self.data.write(T::truncate(self.steps))?;
+            }
+            Op('d') => {
+                let depth = self.stack.len().min(T::MAX.to_usize());
+                self.data.write(T::truncate(depth as u64))?;
+            }
+            Op('k') => {
+                if let Some(&v) = self.stack.last() {
+                    self.data.write(v)?;
+                } else {
+                    self.warn("Cannot peek an empty stack!", "Ignoring.")?;
+                }
+            }
+            Op('?') => {
+                if self.data.read().is_zero() {
+                    self.seek(self.program.after(self.ptr));
+                }
+            }
+            Unknown(c) => {
+                self.check_strict(self.offset(), &format!("Unknown character '{c}'!"))?;
+            }
+            Op(c) => unreachable!("'{c}' is in OPS but has no handler"),
+        }
+        Ok(None)
+    }
 
-                    if self.data.read() == 0 {
-                        self.context_stack.push(Context::While(self.ptr));
-                    } else {
-                        let mut stack_size = 0;
-                        while let Some(c) = self.next_char() {
-                            if c == ']' && stack_size == 0 {
-                                break;
-                            } else if c == ']' {
-                                stack_size -= 1;
-                            } else if c == '[' {
-                                stack_size += 1;
-                            }
-                        }
+    /// Switches to the alternate screen for the debugger, unless it is
+    /// printing frames one after another instead.
+    fn terminal(&self) -> io::Result<Option<TerminalGuard>> {
+        if self.clear {
+            TerminalGuard::new().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn run(&mut self) -> Result<Outcome, VmError> {
+        // An interrupt from an earlier run shouldn't stop this one.
+        INTERRUPTED.store(false, Ordering::Relaxed);
+        self.prepare()?;
+        let mut outcome = Outcome::Finished;
+        self.clear &= io::stdout().is_terminal();
+        let mut terminal = if self.debug { self.terminal()? } else { None };
+
+        loop {
+            let instruction = self.next_instruction();
+
+            if INTERRUPTED.load(Ordering::Relaxed) {
+                return Err(VmError::Interrupted);
+            }
+
+            if self.debug {
+                let running = instruction.is_some()
+                    && self
+                        .run_until_head
+                        .is_some_and(|head| head != self.data.head());
+                if !running {
+                    self.run_until_head = None;
+                    self.debug()?;
+                    if !self.delay.is_zero() {
+                        thread::sleep(self.delay);
+                    } else if self.debug_command()? {
+                        continue;
                     }
                 }
-                'z' => {
-                    if self.current_char() != Some('[') {
-                        error!("'z' should have a ']' after! Ignoring.");
-                    } else {
-                        self.next_char();
-                    }
+                self.record();
+            }
 
-                    if self.data.read() != 0 {
-                        self.context_stack.push(Context::Zero(self.ptr));
+            let Some(instruction) = instruction else {
+                break;
+            };
+            if let Some(max_steps) = self.max_steps
+                && self.steps >= max_steps
+            {
+                let error = VmError::StepLimit {
+                    max_steps,
+                    pos: self.offset(),
+                    tape: self.data.display_window(self.tape_window, &[]),
+                };
+                // Leave the instruction to run if the VM is resumed.
+                self.ptr -= 1;
+                return Err(error);
+            }
+            let steps = self.steps;
+            self.steps += self.program.counts[self.ptr - 1];
+            let head = self.data.head();
+            let result = self.step(instruction);
+            self.notify_tape(head);
+            match result {
+                Ok(None) => self.failure = None,
+                Ok(Some(stop)) => {
+                    outcome = stop;
+                    break;
+                }
+                Err(e) if self.debug_on_error && !matches!(e, VmError::Interrupted) => {
+                    if self.debug {
+                        self.history.pop_back();
                     } else {
-                        let mut stack_size = 0;
-                        while let Some(c) = self.next_char() {
-                            if c == ']' && stack_size == 0 {
-                                break;
-                            } else if c == ']' {
-                                stack_size -= 1;
-                            } else if c == '[' {
-                                stack_size += 1;
-                            }
-                        }
+                        terminal = self.terminal()?;
+                        self.debug = true;
                     }
+                    self.steps = steps;
+                    self.data.set_head(head)?;
+                    self.delay = Duration::ZERO;
+                    self.run_until_head = None;
+                    self.failure = Some(match e.position() {
+                        Some(pos) => format!("{e} ({})", self.source.locate(pos)),
+                        None => e.to_string(),
+                    });
+                    self.seek(self.ptr - 1);
                 }
-                _ => error!("Unknown character '{c}'! Skipping."),
-            }
-
-            if self.debug {
-                io::stdin().read_line(&mut String::new())?;
+                Err(e) => return Err(e),
             }
         }
         if self.debug {
-            self.debug(&stdout)?;
+            drop(terminal);
+            io::stdout().write_all(&self.stdout)?;
+            io::stdout().flush()?;
         }
 
-        Ok(())
+        Ok(outcome)
+    }
+}
+
+fn highlight(c: char) -> ColoredString {
+    let s = c.to_string();
+    match c {
+        'c' | 'C' | 'i' | 's' | 'L' | 'p' | 'P' | 'n' | 'N' | 'v' | 'x' | 'X' | 'o' | 'b' | 'J'
+        | 'g' => s.green(),
+        '+' | '-' | '*' | '/' | '^' | '_' | 'u' | 'A' | 'D' | 'G' | 'B' | 'O' | '{' | '}' => {
+            s.yellow()
+        }
+        'e' | 'f' | 'E' | 'F' | 'w' | 'z' | 'W' | 'm' | '?' | '[' | ']' => s.magenta(),
+        _ => s.normal(),
+    }
+}
+
+fn parse_number(input: &str) -> Option<u32> {
+    if let Some(hex) = input.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = input.strip_prefix("0b") {
+        u32::from_str_radix(binary, 2).ok()
+    } else {
+        input.parse().ok()
     }
 }
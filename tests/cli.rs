@@ -0,0 +1,79 @@
+use std::{
+    env, fs,
+    process::{self, Command, Output},
+};
+
+use serde_json::json;
+
+fn snli(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_snli"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn dump_tape_as_json() {
+    let out = snli(&["-e", "1>>h41<", "--dump-tape=json"]);
+    assert!(out.status.success());
+    let dump: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(dump, json!({ "head": 1, "cells": { "0": 1, "2": 65 } }));
+}
+
+#[test]
+fn dumped_tape_loads_back() {
+    let path = env::temp_dir().join(format!("snli-round-trip-{}.json", process::id()));
+    let path = path.to_str().unwrap();
+    let first = snli(&["-e", "$300#>>7", "--cell-size=16", "--dump-tape=json"]);
+    assert!(first.status.success());
+    fs::write(path, &first.stdout).unwrap();
+    let second = snli(&[
+        "-e",
+        "",
+        "--cell-size=16",
+        "--tape-in",
+        path,
+        "--dump-tape=json",
+    ]);
+    fs::remove_file(path).unwrap();
+    assert!(second.status.success());
+    assert_eq!(second.stdout, first.stdout);
+}
+
+#[test]
+fn dump_a_sparse_tape() {
+    let path = env::temp_dir().join(format!("snli-sparse-{}.json", process::id()));
+    fs::write(&path, r#"{"head":0,"cells":{"0":1,"10000000000":2}}"#).unwrap();
+    let out = snli(&["-e", "", "--tape-in", path.to_str().unwrap(), "--dump-tape"]);
+    fs::remove_file(&path).unwrap();
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8(out.stderr).unwrap(),
+        "01|... 9999999999 empty ... 02|\n^\n"
+    );
+}
+
+#[test]
+fn dump_with_bang() {
+    let out = snli(&["-e", "5>7!"]);
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8(out.stderr).unwrap(),
+        "05|07|\n   ^\nhead 1\n\n"
+    );
+
+    let out = snli(&["-e", "$9$8 5>7!"]);
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8(out.stderr).unwrap(),
+        "05|07|\n   ^\nhead 1\n09|08|\n"
+    );
+}
+
+#[test]
+fn errors_give_a_location() {
+    let out = snli(&["-e", "8\n>0</"]);
+    assert!(!out.status.success());
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(stderr.contains("division by zero! (-e:2:4)"), "{stderr}");
+}
@@ -0,0 +1,18 @@
+//! Kept apart from the other tests, since they would all see the interrupt
+//! flag this sets.
+
+use std::sync::atomic::Ordering;
+
+use snli::{
+    source::Source,
+    vm::{INTERRUPTED, Outcome, Vm},
+};
+
+#[test]
+fn an_earlier_interrupt_does_not_stop_a_new_run() {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+    let source = Source::eval("5n");
+    let mut vm = Vm::<u8>::new(&source, false).with_capture(true);
+    assert_eq!(vm.run().unwrap(), Outcome::Finished);
+    assert_eq!(vm.stdout(), b"5");
+}
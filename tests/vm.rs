@@ -0,0 +1,677 @@
+use snli::{
+    cell::Cell,
+    error::VmError,
+    source::Source,
+    tape::Tape,
+    vm::{Outcome, Vm, VmEvent},
+};
+
+struct Run<T: Cell> {
+    result: Result<Outcome, VmError>,
+    stdout: Vec<u8>,
+    tape: Tape<T>,
+    steps: u64,
+}
+
+impl<T: Cell> Run<T> {
+    fn cells(&self, start: usize, len: usize) -> Vec<T> {
+        self.tape.range(start, len).unwrap()
+    }
+}
+
+/// Runs a program with its output captured, after setting up the VM.
+fn run_with<T: Cell>(src: &str, setup: impl FnOnce(Vm<'_, T>) -> Vm<'_, T>) -> Run<T> {
+    let source = Source::eval(src);
+    let mut vm = setup(Vm::new(&source, false).with_capture(true));
+    let result = vm.run();
+    Run {
+        result,
+        stdout: vm.stdout().to_vec(),
+        tape: vm.tape().clone(),
+        steps: vm.steps(),
+    }
+}
+
+fn run(src: &str) -> Run<u8> {
+    run_with(src, |vm| vm)
+}
+
+#[test]
+fn whitespace_is_not_an_instruction() {
+    let out = run_with::<u8>("1 z[\n\t0\n]\n5 n\n", |vm| vm.with_strict(true));
+    assert!(out.result.is_ok());
+    assert_eq!(out.stdout, b"5");
+    assert_eq!(out.steps, 6);
+
+    assert_eq!(
+        run_with::<u8>("0? 5n", |vm| vm.with_optimize(true)).stdout,
+        b"0"
+    );
+    assert_eq!(run("1t").cells(0, 1), [2]);
+}
+
+#[test]
+fn capture_keeps_raw_bytes() {
+    assert_eq!(run("hffbh41bh0ab").stdout, [0xff, b'A', b'\n']);
+    let out = run_with::<u8>("h80b", |vm| vm.with_capture(false).with_null_output(true));
+    assert!(out.stdout.is_empty());
+}
+
+#[test]
+fn wide_numbers_are_read_into_a_pair() {
+    let out = run_with::<u8>("C", |vm| vm.with_input("1000\n".as_bytes()));
+    assert!(out.result.is_ok());
+    assert_eq!(out.cells(0, 2), [0xe8, 0x03]);
+
+    let out = run_with::<u8>("CN", |vm| vm.with_input("65535\n".as_bytes()));
+    assert_eq!(out.stdout, b"65535");
+
+    let out = run_with::<u8>("1C", |vm| vm.with_input("65536\n".as_bytes()));
+    assert!(matches!(
+        out.result,
+        Err(VmError::BadInput {
+            kind: "number",
+            pos: 1
+        })
+    ));
+    assert_eq!(out.cells(0, 2), [1, 0]);
+}
+
+/// One small program per instruction, with the input it reads, the output it
+/// should print and the cells it should leave at the start of the tape.
+const INSTRUCTIONS: &[(&str, &str, &[u8], &[u8])] = &[
+    ("5", "", b"", &[5]),
+    ("h41", "", b"", &[0x41]),
+    ("$7#", "", b"", &[7]),
+    ("1>2", "", b"", &[1, 2]),
+    ("1>2<3", "", b"", &[3, 2]),
+    ("1>>|2", "", b"", &[2, 0, 0]),
+    ("c", "42\n", b"", &[42]),
+    ("i", "A\n", b"", b"A"),
+    ("C", "258\n", b"", &[2, 1]),
+    ("s", "hi\n", b"", b"hi\0"),
+    ("L", "1 2 3\n", b"", &[1, 2, 3, 0]),
+    ("h41o", "", b"A", &[0x41]),
+    ("h41n", "", b"65", &[0x41]),
+    ("hffv", "", b"-1", &[0xff]),
+    ("h41x", "", b"41", &[0x41]),
+    ("h41b", "", b"A", &[0x41]),
+    ("h41>h42>0<<p", "", b"AB", b"AB\0"),
+    ("h41>2<P", "", b"A\x02", &[0x41, 2]),
+    ("1>1<N", "", b"257", &[1, 1]),
+    ("h41oJh42o", "", b"B", &[0x42]),
+    ("1>2<g", "", b"", &[1, 2]),
+    ("7>3<l", "", b"", &[7, 7, 7]),
+    ("3>4<+", "", b"", &[7, 4]),
+    ("7>4<-", "", b"", &[3, 4]),
+    ("3>4<u", "", b"", &[0xff, 4]),
+    ("3>4<*", "", b"", &[12, 4]),
+    ("2>3<^", "", b"", &[8, 3]),
+    ("8>2</", "", b"", &[4, 2]),
+    ("1>1>2<<A", "", b"", &[3, 1, 2]),
+    ("3>1>2<<D", "", b"", &[1, 1, 2]),
+    ("6>4<G", "", b"", &[2, 4]),
+    ("7B", "", b"", &[3]),
+    ("7O", "", b"", &[1]),
+    ("1_", "", b"", &[0xff]),
+    ("3>1<{", "", b"", &[6, 1]),
+    ("3>1<}", "", b"", &[0x81, 1]),
+    ("2>0>2<<=", "", b"", &[1, 0, 2]),
+    ("1>0>2<<&", "", b"", &[1, 2, 0]),
+    ("1>2>0>2<<<:", "", b"", &[1, 2, 0, 2]),
+    ("3>2<r", "", b"", &[2, 3]),
+    ("1>1<X", "", b"", &[1, 1]),
+    ("0R", "", b"", &[0, b'0']),
+    ("1>1<a", "", b"", &[1, 1]),
+    ("1!~H", "", b"", &[1]),
+    ("V", "", b"", &[0]),
+    ("5@0#", "", b"", &[5]),
+    ("5@0k", "", b"", &[5]),
+    ("5@@d", "", b"", &[2]),
+    ("1>2>0<<S0U", "", b"", &[1, 2, 0]),
+    ("5(0)", "", b"", &[5]),
+    ("1t", "", b"", &[2]),
+    ("0?5", "", b"", &[0]),
+    ("1?5", "", b"", &[5]),
+    ("[1]", "", b"", &[1]),
+    ("1e[2]", "", b"", &[2]),
+    ("0f[2]", "", b"", &[2]),
+    ("3z[>1<-]", "", b"", &[0, 1]),
+    ("w[1]", "", b"", &[1]),
+    ("$1$2W[#]", "", b"", &[1]),
+    ("$1E[5]", "", b"", &[5]),
+    ("F[5]", "", b"", &[5]),
+    ("m[h41[1]*[2]]", "", b"", &[2]),
+];
+
+#[test]
+fn every_instruction_runs() {
+    for &(src, input, stdout, cells) in INSTRUCTIONS {
+        for optimize in [false, true] {
+            let out = run_with::<u8>(src, |vm| {
+                vm.with_input(input.as_bytes())
+                    .with_optimize(optimize)
+                    .with_strict(true)
+            });
+            assert_eq!(out.result.as_ref().unwrap(), &Outcome::Finished, "{src}");
+            assert_eq!(out.stdout, stdout, "{src}");
+            assert_eq!(out.cells(0, cells.len()), cells, "{src}");
+        }
+    }
+}
+
+#[test]
+fn instructions_that_stop_the_program() {
+    assert_eq!(run("3q5").result.unwrap(), Outcome::Quit(3));
+    assert_eq!(run("h4f>0<Q5").result.unwrap(), Outcome::Abort("O".into()));
+    assert!(matches!(
+        run("1>2<a").result,
+        Err(VmError::AssertionFailed {
+            pos: 4,
+            left: 1,
+            right: 2
+        })
+    ));
+}
+
+#[test]
+fn arguments_are_read_by_index() {
+    let args = vec!["ab".to_string(), "c".to_string()];
+    let out = run_with::<u8>("1I", |vm| vm.with_args(args));
+    assert_eq!(out.cells(0, 2), b"c\0");
+    assert_eq!(run("I").cells(0, 1), [0]);
+}
+
+/// Programs that should behave the same with and without the optimizer, with
+/// the input they read.
+const OPTIMIZED: &[(&str, &str)] = &[
+    (include_str!("../programs/hello_world.snl"), ""),
+    (include_str!("../programs/countdown.snl"), ""),
+    (include_str!("../programs/calculator.snl"), "6\n7\n*\n"),
+    (include_str!("../programs/cat.snl"), "hello\nworld\n"),
+    (include_str!("../programs/letters_to_ascii.snl"), "abc\n"),
+    ("5>>>><<<<<<n", ""),
+    ("1>2>3<<z[0]>z[ 0 ]>n", ""),
+    ("1234n0?56n", ""),
+    ("0?>>1", ""),
+    ("1?>>1", ""),
+    ("0?<<1", ""),
+    ("1>><<< <<", ""),
+    ("0?z[0]5", ""),
+    ("5?z[0]n", ""),
+    ("0?z[0]z[0]n", ""),
+    ("hffz[>>>>>>9z[0]<<<<<<>1<-]", ""),
+    ("8>0</", ""),
+];
+
+#[test]
+fn optimizing_does_not_change_behavior() {
+    for &(src, input) in OPTIMIZED {
+        for strict in [false, true] {
+            let [plain, optimized] = [false, true].map(|optimize| {
+                run_with::<u8>(src, |vm| {
+                    vm.with_input(input.as_bytes())
+                        .with_optimize(optimize)
+                        .with_strict(strict)
+                })
+            });
+            assert_eq!(
+                format!("{:?}", plain.result),
+                format!("{:?}", optimized.result),
+                "{src}"
+            );
+            assert_eq!(plain.stdout, optimized.stdout, "{src}");
+            assert_eq!(plain.tape.to_string(), optimized.tape.to_string(), "{src}");
+            assert_eq!(plain.steps, optimized.steps, "{src}");
+        }
+    }
+}
+
+#[test]
+fn strict_mode_stops_at_the_first_warning() {
+    for (src, pos, message) in [
+        ("1#Z\nh10>h10<*n", 1, "Cannot pop from an empty stack!"),
+        ("1Z\nh10>h10<*n", 1, "Unknown character 'Z'!"),
+        ("1\nh10>h10<*n", 10, "Cannot multiply 16 * 16!"),
+        ("1 <h10>h10<*n", 2, "Cannot move left of cell 0!"),
+    ] {
+        let lenient = run(src);
+        assert_eq!(lenient.result.unwrap(), Outcome::Finished, "{src}");
+        assert_eq!(lenient.stdout, b"16", "{src}");
+
+        let strict = run_with::<u8>(src, |vm| vm.with_strict(true));
+        match strict.result {
+            Err(VmError::Strict { message: m, pos: p }) => {
+                assert_eq!((m.as_str(), p), (message, pos), "{src}");
+            }
+            result => panic!("{src} should fail in strict mode, not {result:?}"),
+        }
+        assert!(strict.stdout.is_empty(), "{src}");
+    }
+}
+
+#[test]
+fn hex_literals() {
+    assert_eq!(run("h4a>h4A>hff>h00").cells(0, 4), [0x4a, 0x4a, 0xff, 0]);
+
+    for src in ["7h4n", "7h"] {
+        let lenient = run(src);
+        assert!(lenient.result.is_ok(), "{src}");
+        assert_eq!(lenient.cells(0, 1), [7], "{src}");
+
+        let strict = run_with::<u8>(src, |vm| vm.with_strict(true));
+        assert!(
+            matches!(strict.result, Err(VmError::Strict { pos: 1, .. })),
+            "{src}"
+        );
+    }
+    assert_eq!(run("7h4n").stdout, b"7");
+}
+
+const SWITCH: &str = "m[a[1n]b[e[h62n]2n]c[3n]*[9n]]";
+
+#[test]
+fn switch_runs_the_matching_case() {
+    assert_eq!(run(&format!("h61{SWITCH}")).stdout, b"1");
+    assert_eq!(run(&format!("h62{SWITCH}")).stdout, b"982");
+    assert_eq!(run(&format!("h63{SWITCH}")).stdout, b"3");
+    assert_eq!(run(&format!("h7a{SWITCH}")).stdout, b"9");
+    assert_eq!(run("h7am[a[1n]]5n").stdout, b"5");
+    assert_eq!(run("h62m[a[e[1n]z[2n]]b[7n]]").stdout, b"7");
+}
+
+#[test]
+fn switch_in_a_loop() {
+    let out = run("w[>m[h00[1n]h01[2n]*[3n<1>]]<]");
+    assert!(out.result.is_ok());
+    assert_eq!(out.stdout, b"123");
+    assert_eq!(out.cells(0, 2), [1, 3]);
+}
+
+#[test]
+fn skip_next_instruction() {
+    assert_eq!(run("h41?o").stdout, b"A");
+    assert_eq!(run("0?o5n").stdout, b"5");
+    assert_eq!(run("0?f[5n]7n").stdout, b"7");
+    assert_eq!(run("1>0?f[5n]7n").stdout, b"7");
+    assert_eq!(run("0?h41n").stdout, b"0");
+    assert_eq!(run("0?m[*[5n]]n").stdout, b"0");
+    // `?` just before a closing bracket has nothing to skip.
+    assert_eq!(run("m[h00[?]*[5n]]").stdout, b"");
+    assert_eq!(run("m[h00[7?]*[5n]]n").stdout, b"7");
+    assert_eq!(run("$1$0$0w[#?]dn").stdout, b"0");
+    assert_eq!(run("$0$0$0W[#?]d n").stdout, b"0");
+    for src in ["0?", "1?"] {
+        assert_eq!(run(src).result.unwrap(), Outcome::Finished, "{src}");
+    }
+}
+
+#[test]
+fn power() {
+    assert_eq!(run("2>7<^").cells(0, 2), [128, 7]);
+    for base in [0, 1, 2, 9] {
+        assert_eq!(run(&format!("{base}^")).cells(0, 1), [1], "{base}^0");
+    }
+
+    let overflow = run("2>8<^");
+    assert!(overflow.result.is_ok());
+    assert_eq!(overflow.cells(0, 1), [2]);
+    let strict = run_with::<u8>("2>8<^", |vm| vm.with_strict(true));
+    assert!(matches!(strict.result, Err(VmError::Strict { pos: 4, .. })));
+    assert_eq!(run_with::<u16>("2>8<^", |vm| vm).cells(0, 1), [256]);
+}
+
+#[test]
+fn pair_arithmetic_carries_between_cells() {
+    assert_eq!(run("hff>0>1<<A").cells(0, 4), [0, 1, 1, 0]);
+    assert_eq!(run("hff>2>1<<A").cells(0, 4), [0, 3, 1, 0]);
+    assert_eq!(run("0>1>1<<D").cells(0, 4), [0xff, 0, 1, 0]);
+    assert_eq!(run("5>2>6>1<<<D").cells(0, 4), [0xff, 0, 6, 1]);
+    assert_eq!(run("hff>hff<N").stdout, b"65535");
+
+    assert_eq!(run("hff>hff>1<<A").cells(0, 2), [0xff, 0xff]);
+    assert_eq!(run("0>0>1<<D").cells(0, 2), [0, 0]);
+}
+
+#[test]
+fn gcd() {
+    for (a, b, gcd) in [
+        (0, 0, 0),
+        (0, 5, 5),
+        (5, 0, 5),
+        (12, 18, 6),
+        (17, 5, 1),
+        (48, 180, 12),
+        (255, 85, 85),
+    ] {
+        let out = run(&format!("h{a:02x}>h{b:02x}<G"));
+        assert_eq!(out.cells(0, 2), [gcd, b], "gcd({a}, {b})");
+    }
+}
+
+#[test]
+fn popcount_and_parity() {
+    for value in 0..=255u8 {
+        let out = run(&format!("h{value:02x}B>h{value:02x}O"));
+        assert_eq!(
+            out.cells(0, 2),
+            [value.count_ones() as u8, value % 2],
+            "{value}"
+        );
+    }
+}
+
+#[test]
+fn rotate() {
+    assert_eq!(run("h81>1<{").cells(0, 1), [0x03]);
+    assert_eq!(run("h81>1<}").cells(0, 1), [0xc0]);
+    assert_eq!(run("h81>9<{").cells(0, 1), [0x03]);
+    for amount in [0, 8, 16, 0xf8] {
+        for op in ['{', '}'] {
+            let src = format!("h81>h{amount:02x}<{op}");
+            assert_eq!(run(&src).cells(0, 2), [0x81, amount], "{src}");
+        }
+    }
+    assert_eq!(run_with::<u16>("h81>8<{", |vm| vm).cells(0, 1), [0x8100]);
+}
+
+#[test]
+fn print_region_includes_zeros() {
+    let out = run("h41>4>0>h42<<<Pn");
+    assert_eq!(out.stdout, b"A\x04\0B65");
+    assert_eq!(out.tape.head(), 0);
+    assert_eq!(run("h41>0<Pn").stdout, b"65");
+}
+
+/// A program writing each string followed by a terminator, then moving the
+/// head back to the start.
+fn strings(strings: &[&str]) -> String {
+    let mut src = String::new();
+    for string in strings {
+        for byte in string.bytes() {
+            src += &format!("h{byte:02x}>");
+        }
+        src += "0>";
+    }
+    src + "|"
+}
+
+#[test]
+fn compare_strings() {
+    for (first, second, equal) in [
+        ("ab", "ab", 1),
+        ("ab", "abc", 0),
+        ("abc", "ab", 0),
+        ("ab", "ac", 0),
+        ("", "", 1),
+        ("", "a", 0),
+        ("a", "", 0),
+    ] {
+        let out = run(&(strings(&[first, second]) + "="));
+        assert_eq!(out.cells(0, 1), [equal], "{first:?} = {second:?}");
+        assert_eq!(out.tape.head(), 0);
+    }
+}
+
+#[test]
+fn concatenate_strings() {
+    for (first, second, cells) in [
+        ("ab", "cd", b"abcd\0\0"),
+        ("", "ab", b"ab\0\0\0\0"),
+        ("ab", "", b"ab\0\0\0\0"),
+        ("a", "bcd", b"abcd\0\0"),
+    ] {
+        let out = run(&(strings(&[first, second]) + "&"));
+        assert_eq!(out.cells(0, 6), cells, "{first:?} & {second:?}");
+        assert_eq!(out.tape.head(), 0);
+    }
+}
+
+#[test]
+fn search_strings() {
+    for (haystack, needle, offset) in [
+        ("hello", "llo", 2),
+        ("hello", "h", 0),
+        ("hello", "lol", 255),
+        ("hello", "", 0),
+        ("", "", 0),
+        ("", "a", 255),
+        ("ab", "abc", 255),
+    ] {
+        let out = run(&(strings(&[haystack, needle]) + ":"));
+        assert_eq!(out.cells(0, 1), [offset], "{needle:?} in {haystack:?}");
+        assert_eq!(out.tape.head(), 0);
+    }
+}
+
+#[test]
+fn sort_region() {
+    let out = run("9>5>3>5>1>7|r");
+    assert_eq!(out.cells(0, 6), [1, 3, 5, 5, 9, 7]);
+    assert_eq!(out.tape.head(), 0);
+    assert_eq!(run("9>0>3|r").cells(0, 3), [9, 0, 3]);
+    assert_eq!(run("9>1>0|r").cells(0, 3), [9, 1, 0]);
+}
+
+#[test]
+fn push_and_pop_strings() {
+    // Push "abc" and then "", pop them back in reverse order, and count
+    // what's left on the stack.
+    let out = run(&(strings(&["abc", ""]) + "S>>>>S>>9U>U<<d"));
+    assert_eq!(out.cells(0, 11), b"abc\0\0\0\0abc\0");
+    assert_eq!(out.tape.head(), 5);
+}
+
+#[test]
+fn stack_and_queue_are_separate() {
+    // The stack pops last in first out and the queue first in first out,
+    // however the pushes are interleaved.
+    let out = run("1@2(3@4(>#>)>#>)");
+    assert_eq!(out.cells(0, 5), [4, 3, 2, 1, 4]);
+}
+
+#[test]
+fn while_stack_runs_once_per_value() {
+    // Each pass pops one value and adds it to cell 0; a fourth pass would
+    // pop from an empty stack, which strict mode rejects.
+    let out = run_with::<u8>("$5$6$7W[>#<+]>>d", |vm| vm.with_strict(true));
+    assert_eq!(out.result.as_ref().unwrap(), &Outcome::Finished);
+    assert_eq!(out.cells(0, 3), [18, 5, 0]);
+}
+
+#[test]
+fn stack_conditions() {
+    // An empty stack counts as zero, and testing the top doesn't pop it.
+    for (src, cells) in [
+        ("E[5]>d", [0, 0]),
+        ("F[5]>d", [5, 0]),
+        ("$0E[5]>d", [0, 1]),
+        ("$0F[5]>d", [5, 1]),
+        ("$1E[5]>d", [5, 1]),
+        ("$1F[5]>d", [0, 1]),
+    ] {
+        let out = run_with::<u8>(src, |vm| vm.with_strict(true));
+        assert_eq!(out.result.as_ref().unwrap(), &Outcome::Finished, "{src}");
+        assert_eq!(out.cells(0, 2), cells, "{src}");
+    }
+}
+
+#[test]
+fn read_own_source() {
+    let out = run("0R>>1R>>2R>>hffR");
+    assert_eq!(out.cells(0, 8), [0, b'0', 1, b'R', 2, b'>', 0xff, 0]);
+}
+
+#[test]
+fn step_counter() {
+    assert_eq!(run("1>2>3>t").cells(3, 1), [7]);
+    // As the last instruction, `t` sees every step the program took, loops
+    // included, whether or not they were optimized.
+    for optimize in [false, true] {
+        let out = run_with::<u8>("3z[>1<-]>>t", |vm| vm.with_optimize(optimize));
+        assert_eq!(u64::from(out.cells(2, 1)[0]), out.steps, "{optimize}");
+    }
+}
+
+#[test]
+fn assertions() {
+    let out = run("1>1<a7");
+    assert_eq!(out.result.as_ref().unwrap(), &Outcome::Finished);
+    assert_eq!(out.cells(0, 2), [7, 1]);
+
+    // A failed assertion stops the program before the `7`.
+    let out = run("1>2<a7");
+    assert!(matches!(
+        out.result,
+        Err(VmError::AssertionFailed {
+            pos: 4,
+            left: 1,
+            right: 2
+        })
+    ));
+    assert_eq!(out.cells(0, 2), [1, 2]);
+}
+
+#[test]
+fn read_string_keeps_utf8_bytes() {
+    let out = run_with::<u8>("s", |vm| vm.with_input("é😀\n".as_bytes()));
+    assert_eq!(out.cells(0, 7), "é😀\0".as_bytes());
+    assert_eq!(out.tape.head(), 0);
+}
+
+#[test]
+fn moving_left_of_cell_0_stays_there() {
+    let out = run("<1>n");
+    assert_eq!(out.result.as_ref().unwrap(), &Outcome::Finished);
+    assert_eq!(out.stdout, b"0");
+    assert_eq!(out.cells(0, 2), [1, 0]);
+
+    let out = run_with::<u8>("<1>n", |vm| vm.with_strict(true));
+    assert!(matches!(out.result, Err(VmError::Strict { pos: 0, .. })));
+}
+
+#[test]
+fn wide_cells_count_past_255() {
+    let src = "$300#>1>>1<<<z[>>+<<-]>>n";
+    let out = run_with::<u16>(src, |vm| vm);
+    assert_eq!(out.result.as_ref().unwrap(), &Outcome::Finished);
+    assert_eq!(out.stdout, b"300");
+    assert_eq!(out.cells(0, 4), [0, 1, 300, 1]);
+}
+
+#[test]
+fn max_cells_stops_a_greedy_program() {
+    let out = run_with::<u8>("1z[>1]", |vm| vm.with_max_cells(Some(4)));
+    assert!(matches!(
+        out.result,
+        Err(VmError::TapeFull {
+            max_cells: 4,
+            index: 4
+        })
+    ));
+    assert_eq!(out.cells(0, 5), [1, 1, 1, 1, 0]);
+}
+
+#[test]
+fn echo_a_line() {
+    let out = run_with::<u8>("sp", |vm| vm.with_input(&b"hey\n"[..]));
+    assert_eq!(out.stdout, b"hey");
+    assert_eq!(out.cells(0, 4), b"hey\0");
+    assert_eq!(out.tape.head(), 0);
+}
+
+#[test]
+fn restoring_a_snapshot_replays_the_same_run() {
+    let source = Source::eval("5z[n>1<-]");
+    let mut vm = Vm::<u8>::new(&source, false)
+        .with_capture(true)
+        .with_max_steps(Some(10));
+    assert!(matches!(vm.run(), Err(VmError::StepLimit { .. })));
+    let snapshot = vm.snapshot();
+
+    let mut vm = vm.with_max_steps(None);
+    assert_eq!(vm.run().unwrap(), Outcome::Finished);
+    let first = (vm.stdout().to_vec(), vm.tape().clone(), vm.steps());
+    assert_eq!(first.0, b"54321");
+
+    vm.restore(&snapshot);
+    assert_eq!(vm.run().unwrap(), Outcome::Finished);
+    assert_eq!(vm.stdout(), first.0);
+    assert_eq!(vm.tape().to_json(), first.1.to_json());
+    assert_eq!(vm.steps(), first.2);
+}
+
+#[test]
+fn step_limit_stops_an_infinite_loop() {
+    for optimize in [false, true] {
+        let out = run_with::<u8>("1z[>1<]", |vm| {
+            vm.with_optimize(optimize).with_max_steps(Some(50))
+        });
+        assert!(
+            matches!(out.result, Err(VmError::StepLimit { max_steps: 50, .. })),
+            "{optimize}"
+        );
+        // The last instruction run may have been several fused together.
+        assert!((50..55).contains(&out.steps), "{optimize}");
+    }
+
+    // Only the cells around the head go in the error, however far the
+    // program got.
+    let out = run_with::<u8>("1z[>1]", |vm| vm.with_max_steps(Some(200_000)));
+    let Err(VmError::StepLimit { tape, .. }) = out.result else {
+        panic!("expected a step limit, got {:?}", out.result);
+    };
+    assert!(tape.len() < 500, "{tape}");
+    assert!(tape.contains(&format!("^ {}", out.tape.head())), "{tape}");
+}
+
+#[test]
+fn wrapping_tape() {
+    fn wrap(vm: Vm<'_, u8>) -> Vm<'_, u8> {
+        vm.with_tape_size(Some(3)).with_wrap(true)
+    }
+    let out = run_with::<u8>("1>>>2", wrap);
+    assert_eq!(out.result.as_ref().unwrap(), &Outcome::Finished);
+    assert_eq!((out.tape.head(), out.cells(0, 3)), (0, vec![2, 0, 0]));
+
+    let out = run_with::<u8>("1<5<6", wrap);
+    assert_eq!(out.result.as_ref().unwrap(), &Outcome::Finished);
+    assert_eq!((out.tape.head(), out.cells(0, 3)), (1, vec![1, 6, 5]));
+
+    // The tape keeps the wrap setting whichever is set first.
+    let out = run_with::<u8>("1>>>2", |vm| vm.with_wrap(true).with_tape_size(Some(3)));
+    assert_eq!((out.tape.head(), out.cells(0, 3)), (0, vec![2, 0, 0]));
+}
+
+#[test]
+fn fixed_tape_overflows() {
+    let out = run_with::<u8>("1>>>2", |vm| vm.with_tape_size(Some(3)));
+    assert!(matches!(out.result, Err(VmError::TapeOverflow { size: 3 })));
+    assert_eq!((out.tape.head(), out.cells(0, 3)), (2, vec![1, 0, 0]));
+}
+
+#[test]
+fn observer_sees_each_event() {
+    let source = Source::eval("1>hffb<z[0]");
+    let mut events = Vec::new();
+    let mut vm = Vm::<u8>::new(&source, false)
+        .with_capture(true)
+        .with_observer(|event| events.push(event.clone()));
+    assert_eq!(vm.run().unwrap(), Outcome::Finished);
+    drop(vm);
+    assert_eq!(
+        events,
+        [
+            VmEvent::CellWritten { index: 0, value: 1 },
+            VmEvent::HeadMoved { from: 0, to: 1 },
+            VmEvent::CellWritten {
+                index: 1,
+                value: 0xff
+            },
+            VmEvent::Output(vec![0xff]),
+            VmEvent::HeadMoved { from: 1, to: 0 },
+            VmEvent::LoopEntered { pos: 7 },
+            VmEvent::CellWritten { index: 0, value: 0 },
+        ]
+    );
+}